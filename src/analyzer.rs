@@ -0,0 +1,285 @@
+// Spectrum and chroma analysis of the input and output, handed to the editor
+
+use realfft::num_complex::Complex32;
+use realfft::{RealFftPlanner, RealToComplex};
+use std::f32::consts::PI;
+use std::sync::Arc;
+use triple_buffer::{triple_buffer, Input, Output};
+
+/// Size of the analysis frames. At 44.1 kHz the bins are 5.4 Hz apart, enough to tell the
+/// pitch classes apart from A2 up.
+pub const ANALYSIS_SIZE: usize = 8192;
+pub const NUM_BINS: usize = ANALYSIS_SIZE / 2 + 1;
+/// The analysis is updated this many times per second.
+const UPDATE_RATE: f32 = 30.0;
+/// The range of frequencies that count towards the chroma.
+const CHROMA_MIN_FREQ: f32 = 110.0;
+const CHROMA_MAX_FREQ: f32 = 5000.0;
+/// Levels are clamped to this many decibels so silence stays finite.
+pub const FLOOR_DB: f32 = -120.0;
+
+/// The spectra and chroma of the input and output at the time of the last update.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Analysis {
+    pub sample_rate: f32,
+    /// Level of every FFT bin in dBFS. A full scale sine in the middle of a bin reads 0 dB.
+    pub input_spectrum: Vec<f32>,
+    pub output_spectrum: Vec<f32>,
+    /// Energy of every pitch class from C to B in dBFS, summed over all octaves
+    pub input_chroma: [f32; 12],
+    pub output_chroma: [f32; 12],
+}
+
+impl Analysis {
+    fn new(sample_rate: f32) -> Self {
+        Self {
+            sample_rate,
+            input_spectrum: vec![FLOOR_DB; NUM_BINS],
+            output_spectrum: vec![FLOOR_DB; NUM_BINS],
+            input_chroma: [FLOOR_DB; 12],
+            output_chroma: [FLOOR_DB; 12],
+        }
+    }
+
+    /// The centre frequency of bin `k`.
+    pub fn bin_freq(&self, k: usize) -> f32 {
+        k as f32 * self.sample_rate / ANALYSIS_SIZE as f32
+    }
+
+    /// How much louder every pitch class is in the output than in the input, in decibels.
+    pub fn emphasis_db(&self) -> [f32; 12] {
+        std::array::from_fn(|i| self.output_chroma[i] - self.input_chroma[i])
+    }
+}
+
+/// A windowed FFT with preallocated buffers.
+struct Fft {
+    fft: Arc<dyn RealToComplex<f32>>,
+    /// Hann window, scaled so a full scale sine reads 0 dB
+    window: Vec<f32>,
+    frame: Vec<f32>,
+    spectrum: Vec<Complex32>,
+    scratch: Vec<Complex32>,
+}
+
+impl Fft {
+    fn new() -> Self {
+        let fft = RealFftPlanner::<f32>::new().plan_fft_forward(ANALYSIS_SIZE);
+        // The window's coherent gain is 1/2, and a sine's energy is split between the positive
+        // and negative frequencies
+        let window = (0..ANALYSIS_SIZE)
+            .map(|n| {
+                let hann = 0.5 - 0.5 * (2.0 * PI * n as f32 / ANALYSIS_SIZE as f32).cos();
+                hann * 4.0 / ANALYSIS_SIZE as f32
+            })
+            .collect();
+        Self {
+            frame: fft.make_input_vec(),
+            spectrum: fft.make_output_vec(),
+            scratch: fft.make_scratch_vec(),
+            fft,
+            window,
+        }
+    }
+
+    /// Analyze the ring buffer `ring` whose oldest sample is at `pos`. `reference` is the
+    /// frequency of A4 the pitch classes are measured against.
+    fn analyze(
+        &mut self,
+        ring: &[f32],
+        pos: usize,
+        bin_width: f32,
+        reference: f32,
+        spectrum_db: &mut [f32],
+        chroma_db: &mut [f32; 12],
+    ) {
+        for (i, (sample, w)) in self.frame.iter_mut().zip(self.window.iter()).enumerate() {
+            *sample = ring[(pos + i) % ANALYSIS_SIZE] * w;
+        }
+        if self
+            .fft
+            .process_with_scratch(&mut self.frame, &mut self.spectrum, &mut self.scratch)
+            .is_err()
+        {
+            return;
+        }
+
+        let mut chroma = [0.0; 12];
+        for (k, (bin, db)) in self.spectrum.iter().zip(spectrum_db.iter_mut()).enumerate() {
+            let power = bin.norm_sqr();
+            *db = (10.0 * power.log10()).max(FLOOR_DB);
+            let freq = k as f32 * bin_width;
+            if (CHROMA_MIN_FREQ..CHROMA_MAX_FREQ).contains(&freq) {
+                let midi = 69.0 + 12.0 * (freq / reference).log2();
+                chroma[(midi.round() as i32).rem_euclid(12) as usize] += power;
+            }
+        }
+        for (db, power) in chroma_db.iter_mut().zip(chroma) {
+            *db = (10.0 * power.log10()).max(FLOOR_DB);
+        }
+    }
+}
+
+/// Collects the mono input and output and publishes their analysis through a triple buffer,
+/// so neither the audio thread nor the editor ever waits for the other.
+pub struct Analyzer {
+    sample_rate: f32,
+    reference: f32,
+    fft: Fft,
+    input: Vec<f32>,
+    output: Vec<f32>,
+    input_pos: usize,
+    output_pos: usize,
+    /// Samples between two updates
+    interval: usize,
+    countdown: usize,
+    publisher: Input<Analysis>,
+}
+
+impl Analyzer {
+    /// Create the analyzer and the output the editor reads from.
+    pub fn new(sample_rate: f32) -> (Self, Output<Analysis>) {
+        let (publisher, output) = triple_buffer(&Analysis::new(sample_rate));
+        let analyzer = Self {
+            sample_rate,
+            reference: 440.0,
+            fft: Fft::new(),
+            input: vec![0.0; ANALYSIS_SIZE],
+            output: vec![0.0; ANALYSIS_SIZE],
+            input_pos: 0,
+            output_pos: 0,
+            interval: (sample_rate / UPDATE_RATE) as usize,
+            countdown: 0,
+            publisher,
+        };
+        (analyzer, output)
+    }
+
+    /// Change the sample rate. This clears the collected signals.
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.interval = (sample_rate / UPDATE_RATE) as usize;
+        self.reset();
+    }
+
+    /// Set the frequency of A4 the chroma is measured against.
+    pub fn set_reference(&mut self, reference: f32) {
+        self.reference = reference;
+    }
+
+    pub fn reset(&mut self) {
+        self.input.fill(0.0);
+        self.output.fill(0.0);
+        self.input_pos = 0;
+        self.output_pos = 0;
+        self.countdown = 0;
+    }
+
+    pub fn push_input(&mut self, sample: f32) {
+        self.input[self.input_pos] = sample;
+        self.input_pos = (self.input_pos + 1) % ANALYSIS_SIZE;
+    }
+
+    pub fn push_output(&mut self, sample: f32) {
+        self.output[self.output_pos] = sample;
+        self.output_pos = (self.output_pos + 1) % ANALYSIS_SIZE;
+    }
+
+    /// Call after pushing a block of `num_samples` samples. Publishes a new analysis
+    /// `UPDATE_RATE` times per second. This doesn't allocate.
+    pub fn update(&mut self, num_samples: usize) {
+        self.countdown = self.countdown.saturating_sub(num_samples);
+        if self.countdown > 0 {
+            return;
+        }
+        self.countdown = self.interval;
+
+        let bin_width = self.sample_rate / ANALYSIS_SIZE as f32;
+        let analysis = self.publisher.input_buffer();
+        analysis.sample_rate = self.sample_rate;
+        self.fft.analyze(
+            &self.input,
+            self.input_pos,
+            bin_width,
+            self.reference,
+            &mut analysis.input_spectrum,
+            &mut analysis.input_chroma,
+        );
+        self.fft.analyze(
+            &self.output,
+            self.output_pos,
+            bin_width,
+            self.reference,
+            &mut analysis.output_spectrum,
+            &mut analysis.output_chroma,
+        );
+        self.publisher.publish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(freq: f32, n: usize, sr: f32) -> f32 {
+        (2.0 * PI * freq * n as f32 / sr).sin()
+    }
+
+    #[test]
+    fn test_spectrum_of_sine() {
+        let sr = 44100.0;
+        let (mut analyzer, mut output) = Analyzer::new(sr);
+        // The centre of bin 100
+        let freq = 100.0 * sr / ANALYSIS_SIZE as f32;
+        for n in 0..ANALYSIS_SIZE {
+            analyzer.push_input(sine(freq, n, sr));
+            analyzer.push_output(0.5 * sine(freq, n, sr));
+        }
+        analyzer.update(ANALYSIS_SIZE);
+        let analysis = output.read();
+        assert!(analysis.input_spectrum[100].abs() < 0.1);
+        assert!((analysis.output_spectrum[100] + 6.02).abs() < 0.1);
+        assert!(analysis.input_spectrum[200] < -100.0);
+        assert!((analysis.bin_freq(100) - freq).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_chroma_follows_pitch_classes() {
+        let sr = 44100.0;
+        let (mut analyzer, mut output) = Analyzer::new(sr);
+        // A C major triad in, only the A above it out
+        for n in 0..ANALYSIS_SIZE {
+            let a = sine(440.0, n, sr);
+            let triad = sine(261.63, n, sr) + sine(329.63, n, sr) + sine(392.0, n, sr);
+            analyzer.push_input(triad + 0.1 * a);
+            analyzer.push_output(a);
+        }
+        analyzer.update(ANALYSIS_SIZE);
+        let analysis = output.read();
+        let loudest = |chroma: &[f32; 12]| {
+            (0..12)
+                .max_by(|a, b| chroma[*a].total_cmp(&chroma[*b]))
+                .unwrap()
+        };
+        assert_eq!(loudest(&analysis.output_chroma), 9);
+        for note in [0, 4, 7] {
+            assert!(analysis.input_chroma[note] > analysis.input_chroma[9] + 10.0);
+        }
+        let emphasis = analysis.emphasis_db();
+        assert!((emphasis[9] - 20.0).abs() < 1.0, "{}", emphasis[9]);
+        assert!(emphasis[0] < -40.0);
+    }
+
+    #[test]
+    fn test_updates_at_rate() {
+        let sr = 44100.0;
+        let (mut analyzer, mut output) = Analyzer::new(sr);
+        analyzer.update(64);
+        assert!(output.updated());
+        output.read();
+        analyzer.update(64);
+        assert!(!output.updated());
+        analyzer.update(sr as usize);
+        assert!(output.updated());
+    }
+}
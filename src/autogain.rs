@@ -0,0 +1,113 @@
+// Automatic gain compensation matching the wet signal's loudness to the dry signal
+
+/// Levels below this power are treated as silence, so fades into silence don't get boosted.
+const SILENCE_POWER: f32 = 1e-8;
+/// The compensation gain is kept within +-40 dB.
+const MIN_GAIN: f32 = 0.01;
+const MAX_GAIN: f32 = 100.0;
+
+/// Follows the RMS level of the dry and wet signals and returns the gain that brings the wet
+/// signal to the dry signal's level.
+#[derive(Clone, Debug)]
+pub struct AutoGain {
+    sample_rate: f32,
+    time_ms: f32,
+    coeff: f32,
+    dry_power: f32,
+    wet_power: f32,
+}
+
+impl AutoGain {
+    /// `time_ms` is the time constant of the RMS detectors.
+    pub fn new(sample_rate: f32, time_ms: f32) -> Self {
+        let mut autogain = Self {
+            sample_rate,
+            time_ms: 0.0,
+            coeff: 0.0,
+            dry_power: 0.0,
+            wet_power: 0.0,
+        };
+        autogain.set_time(time_ms);
+        autogain
+    }
+
+    pub fn set_time(&mut self, time_ms: f32) {
+        if time_ms == self.time_ms {
+            return;
+        }
+        self.time_ms = time_ms;
+        self.coeff = (-1000.0 / (time_ms.max(0.1) * self.sample_rate)).exp();
+    }
+
+    pub fn reset(&mut self) {
+        self.dry_power = 0.0;
+        self.wet_power = 0.0;
+    }
+
+    /// Update the detectors and return the compensation gain for `wet`.
+    pub fn process(&mut self, dry: f32, wet: f32) -> f32 {
+        self.dry_power = dry * dry + self.coeff * (self.dry_power - dry * dry);
+        self.wet_power = wet * wet + self.coeff * (self.wet_power - wet * wet);
+        self.gain()
+    }
+
+    /// The current compensation gain.
+    pub fn gain(&self) -> f32 {
+        ((self.dry_power + SILENCE_POWER) / (self.wet_power + SILENCE_POWER))
+            .sqrt()
+            .clamp(MIN_GAIN, MAX_GAIN)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    fn sine(n: usize) -> f32 {
+        (2.0 * PI * 440.0 * n as f32 / 44100.0).sin()
+    }
+
+    #[test]
+    fn test_matches_levels() {
+        let mut autogain = AutoGain::new(44100.0, 100.0);
+        let mut gain = 0.0;
+        for n in 0..44100 {
+            gain = autogain.process(sine(n), 10.0 * sine(n));
+        }
+        assert!((gain - 0.1).abs() < 0.005, "{gain}");
+    }
+
+    #[test]
+    fn test_silence_is_unity() {
+        let mut autogain = AutoGain::new(44100.0, 100.0);
+        for _ in 0..1000 {
+            autogain.process(0.0, 0.0);
+        }
+        assert_eq!(autogain.gain(), 1.0);
+    }
+
+    #[test]
+    fn test_gain_is_limited() {
+        let mut autogain = AutoGain::new(44100.0, 10.0);
+        for n in 0..44100 {
+            autogain.process(sine(n), 1e-4 * sine(n));
+        }
+        assert_eq!(autogain.gain(), MAX_GAIN);
+    }
+
+    #[test]
+    fn test_time_constant() {
+        // Time until the gain follows the wet signal jumping up by 12 dB
+        let settle = |time_ms: f32| {
+            let mut autogain = AutoGain::new(44100.0, time_ms);
+            for n in 0..44100 {
+                autogain.process(sine(n), sine(n));
+            }
+            (44100..88200)
+                .position(|n| autogain.process(sine(n), 4.0 * sine(n)) < 0.3)
+                .unwrap()
+        };
+        assert!(settle(10.0) < settle(200.0));
+    }
+}
@@ -0,0 +1,392 @@
+// Chord symbol parsing for the chord mask mode
+
+use std::fmt;
+
+/// Chord list used when no list has been stored in the plugin state yet.
+pub const DEFAULT_CHORDS: [&str; 8] = [
+    "Cmaj7", "Am7", "Dm7", "G7", "Fmaj7", "Em7", "F#m7b5", "Bb13",
+];
+
+/// The role a pitch plays in a chord. Each role gets its own weight.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChordTone {
+    Root,
+    Third,
+    Fifth,
+    Extension,
+}
+
+/// Weights applied to the chord tones when building a note mask.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChordWeights {
+    pub root: f32,
+    pub third: f32,
+    pub fifth: f32,
+    pub extensions: f32,
+}
+
+impl Default for ChordWeights {
+    fn default() -> Self {
+        Self {
+            root: 1.0,
+            third: 1.0,
+            fifth: 1.0,
+            extensions: 1.0,
+        }
+    }
+}
+
+impl ChordWeights {
+    fn weight(&self, tone: ChordTone) -> f32 {
+        match tone {
+            ChordTone::Root => self.root,
+            ChordTone::Third => self.third,
+            ChordTone::Fifth => self.fifth,
+            ChordTone::Extension => self.extensions,
+        }
+    }
+}
+
+/// Error returned when a chord symbol can't be parsed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChordError {
+    Empty,
+    InvalidRoot,
+    InvalidBass,
+    UnknownSuffix,
+}
+
+impl fmt::Display for ChordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChordError::Empty => write!(f, "empty chord symbol"),
+            ChordError::InvalidRoot => write!(f, "chord symbol must start with a note from A to G"),
+            ChordError::InvalidBass => write!(f, "invalid bass note after '/'"),
+            ChordError::UnknownSuffix => write!(f, "unknown chord quality or extension"),
+        }
+    }
+}
+
+impl std::error::Error for ChordError {}
+
+/// A parsed chord. `tones` is indexed by the interval above the root.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Chord {
+    pub root: u8,
+    pub bass: Option<u8>,
+    pub tones: [Option<ChordTone>; 12],
+}
+
+impl Chord {
+    /// Build the twelve note gains (C..B) enabling exactly the chord's pitch classes.
+    pub fn mask(&self, weights: ChordWeights) -> [f32; 12] {
+        let mut mask = [0.0_f32; 12];
+        for (interval, tone) in self.tones.iter().enumerate() {
+            if let Some(tone) = tone {
+                let idx = (self.root as usize + interval) % 12;
+                mask[idx] = mask[idx].max(weights.weight(*tone));
+            }
+        }
+        if let Some(bass) = self.bass {
+            let idx = bass as usize;
+            mask[idx] = mask[idx].max(weights.root);
+        }
+        mask
+    }
+}
+
+/// Parse a note name with optional accidentals, e.g. `F#` or `Bb`. Returns the pitch class and
+/// the remaining input.
+fn parse_note(input: &str) -> Option<(u8, &str)> {
+    let mut chars = input.chars();
+    let base: i32 = match chars.next()?.to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+    let mut offset = 0;
+    let mut rest = chars.as_str();
+    loop {
+        if let Some(r) = rest.strip_prefix('#').or_else(|| rest.strip_prefix('♯')) {
+            offset += 1;
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix('b').or_else(|| rest.strip_prefix('♭')) {
+            offset -= 1;
+            rest = r;
+        } else {
+            break;
+        }
+    }
+    Some(((base + offset).rem_euclid(12) as u8, rest))
+}
+
+/// Strip the first matching prefix from `input`.
+fn strip_any<'a>(input: &'a str, prefixes: &[&str]) -> Option<&'a str> {
+    prefixes.iter().find_map(|p| input.strip_prefix(p))
+}
+
+/// Parse a chord symbol such as `Cmaj7`, `F#m7b5`, `Bb13` or `Dsus4/A`.
+///
+/// This does not allocate so it can be used on the audio thread.
+pub fn parse_chord(symbol: &str) -> Result<Chord, ChordError> {
+    let symbol = symbol.trim();
+    if symbol.is_empty() {
+        return Err(ChordError::Empty);
+    }
+
+    // `6/9` is part of the chord name rather than a slash chord
+    let (main, bass) = match symbol.rsplit_once('/') {
+        Some((main, bass)) if !bass.trim().starts_with(|c: char| c.is_ascii_digit()) => {
+            let (note, rest) = parse_note(bass.trim()).ok_or(ChordError::InvalidBass)?;
+            if !rest.is_empty() {
+                return Err(ChordError::InvalidBass);
+            }
+            (main, Some(note))
+        }
+        _ => (symbol, None),
+    };
+
+    let (root, mut rest) = parse_note(main).ok_or(ChordError::InvalidRoot)?;
+
+    let mut tones = [None; 12];
+    tones[0] = Some(ChordTone::Root);
+    let mut third = Some(4);
+    let mut fifth = 7;
+    let mut major_seventh = false;
+    let mut diminished = false;
+    let mut seventh = None;
+
+    // Chord quality. `+5` and `-5` right after the root alter the fifth of a major triad and
+    // are left to the alterations below, rather than read as an augmented or minor quality
+    // followed by a power chord.
+    if rest.starts_with("+5") || rest.starts_with("-5") {
+    } else if let Some(r) = strip_any(rest, &["maj", "Maj", "ma", "M", "Δ"]) {
+        major_seventh = true;
+        rest = r;
+    } else if let Some(r) = strip_any(rest, &["min", "mi", "m", "-"]) {
+        third = Some(3);
+        rest = r;
+        // Minor-major chords like `CmMaj7`
+        if let Some(r) = strip_any(rest, &["(maj", "Maj", "maj", "M"]) {
+            major_seventh = true;
+            rest = r;
+        }
+    } else if let Some(r) = strip_any(rest, &["dim", "°", "o"]) {
+        third = Some(3);
+        fifth = 6;
+        diminished = true;
+        rest = r;
+    } else if let Some(r) = strip_any(rest, &["aug", "+"]) {
+        fifth = 8;
+        rest = r;
+    } else if let Some(r) = rest.strip_prefix('ø') {
+        third = Some(3);
+        fifth = 6;
+        seventh = Some(10);
+        rest = r;
+    }
+
+    let seventh_interval = if major_seventh {
+        11
+    } else if diminished {
+        9
+    } else {
+        10
+    };
+
+    // Main chord number. Longer numbers are matched first so `13` isn't read as `1`.
+    if let Some(r) = rest.strip_prefix("13") {
+        seventh = Some(seventh_interval);
+        tones[2] = Some(ChordTone::Extension);
+        tones[9] = Some(ChordTone::Extension);
+        if third == Some(3) {
+            tones[5] = Some(ChordTone::Extension);
+        }
+        rest = r;
+    } else if let Some(r) = rest.strip_prefix("11") {
+        seventh = Some(seventh_interval);
+        tones[2] = Some(ChordTone::Extension);
+        tones[5] = Some(ChordTone::Extension);
+        rest = r;
+    } else if let Some(r) = rest.strip_prefix('9') {
+        seventh = Some(seventh_interval);
+        tones[2] = Some(ChordTone::Extension);
+        rest = r;
+    } else if let Some(r) = rest.strip_prefix('7') {
+        seventh = Some(seventh_interval);
+        rest = r;
+    } else if let Some(r) = rest.strip_prefix("6/9").or_else(|| rest.strip_prefix("69")) {
+        tones[9] = Some(ChordTone::Extension);
+        tones[2] = Some(ChordTone::Extension);
+        rest = r;
+    } else if let Some(r) = rest.strip_prefix('6') {
+        tones[9] = Some(ChordTone::Extension);
+        rest = r;
+    } else if let Some(r) = rest.strip_prefix('5') {
+        // Power chord
+        third = None;
+        rest = r;
+    }
+
+    // Suspensions, additions and alterations
+    loop {
+        rest = rest.trim_start_matches(['(', ')', ',', ' ']);
+        if rest.is_empty() {
+            break;
+        }
+
+        if let Some(r) = rest.strip_prefix("sus2") {
+            third = Some(2);
+            rest = r;
+        } else if let Some(r) = strip_any(rest, &["sus4", "sus"]) {
+            third = Some(5);
+            rest = r;
+        } else if let Some(r) = strip_any(rest, &["add9", "add2"]) {
+            tones[2] = Some(ChordTone::Extension);
+            rest = r;
+        } else if let Some(r) = strip_any(rest, &["add11", "add4"]) {
+            tones[5] = Some(ChordTone::Extension);
+            rest = r;
+        } else if let Some(r) = strip_any(rest, &["add13", "add6"]) {
+            tones[9] = Some(ChordTone::Extension);
+            rest = r;
+        } else if let Some(r) = strip_any(rest, &["b5", "-5"]) {
+            fifth = 6;
+            rest = r;
+        } else if let Some(r) = strip_any(rest, &["#5", "+5"]) {
+            fifth = 8;
+            rest = r;
+        } else if let Some(r) = strip_any(rest, &["b9", "-9"]) {
+            tones[1] = Some(ChordTone::Extension);
+            rest = r;
+        } else if let Some(r) = strip_any(rest, &["#9", "+9"]) {
+            tones[3] = Some(ChordTone::Extension);
+            rest = r;
+        } else if let Some(r) = strip_any(rest, &["#11", "+11"]) {
+            tones[6] = Some(ChordTone::Extension);
+            rest = r;
+        } else if let Some(r) = strip_any(rest, &["b13", "-13"]) {
+            tones[8] = Some(ChordTone::Extension);
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix("13") {
+            tones[9] = Some(ChordTone::Extension);
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix("11") {
+            tones[5] = Some(ChordTone::Extension);
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix('9') {
+            tones[2] = Some(ChordTone::Extension);
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix('7') {
+            // The `7` in `m(maj7)` and similar spellings
+            seventh = Some(seventh_interval);
+            rest = r;
+        } else {
+            return Err(ChordError::UnknownSuffix);
+        }
+    }
+
+    if let Some(third) = third {
+        tones[third] = Some(ChordTone::Third);
+    }
+    tones[fifth] = Some(ChordTone::Fifth);
+    if let Some(seventh) = seventh {
+        tones[seventh] = Some(ChordTone::Extension);
+    }
+
+    Ok(Chord { root, bass, tones })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pitch_classes(symbol: &str) -> Vec<usize> {
+        let mask = parse_chord(symbol).unwrap().mask(ChordWeights::default());
+        (0..12).filter(|i| mask[*i] > 0.0).collect()
+    }
+
+    #[test]
+    fn test_triads() {
+        assert_eq!(pitch_classes("C"), vec![0, 4, 7]);
+        assert_eq!(pitch_classes("Am"), vec![0, 4, 9]);
+        assert_eq!(pitch_classes("Bdim"), vec![2, 5, 11]);
+        assert_eq!(pitch_classes("Caug"), vec![0, 4, 8]);
+        assert_eq!(pitch_classes("Dsus4"), vec![2, 7, 9]);
+        assert_eq!(pitch_classes("E5"), vec![4, 11]);
+        assert_eq!(pitch_classes("C#5"), vec![1, 8]);
+    }
+
+    #[test]
+    fn test_altered_fifths() {
+        assert_eq!(pitch_classes("C+5"), vec![0, 4, 8]);
+        assert_eq!(pitch_classes("C-5"), vec![0, 4, 6]);
+        // Straight after the root, `#` and `b` belong to the root
+        assert_eq!(pitch_classes("Cb5"), vec![6, 11]);
+        assert_eq!(pitch_classes("Cm#5"), vec![0, 3, 8]);
+        assert_eq!(pitch_classes("Cmb5"), vec![0, 3, 6]);
+        assert_eq!(pitch_classes("C7-5"), vec![0, 4, 6, 10]);
+        assert_eq!(pitch_classes("C7+5"), vec![0, 4, 8, 10]);
+        assert_eq!(pitch_classes("Cmaj7#5"), vec![0, 4, 8, 11]);
+    }
+
+    #[test]
+    fn test_seventh_chords() {
+        assert_eq!(pitch_classes("Cmaj7"), vec![0, 4, 7, 11]);
+        assert_eq!(pitch_classes("G7"), vec![2, 5, 7, 11]);
+        assert_eq!(pitch_classes("F#m7b5"), vec![0, 4, 6, 9]);
+        assert_eq!(pitch_classes("Bdim7"), vec![2, 5, 8, 11]);
+        assert_eq!(pitch_classes("CmMaj7"), vec![0, 3, 7, 11]);
+    }
+
+    #[test]
+    fn test_extended_chords() {
+        // Bb D F Ab C G
+        assert_eq!(pitch_classes("Bb13"), vec![0, 2, 5, 7, 8, 10]);
+        assert_eq!(pitch_classes("C7#9"), vec![0, 3, 4, 7, 10]);
+        assert_eq!(pitch_classes("Cadd9"), vec![0, 2, 4, 7]);
+        assert_eq!(pitch_classes("C6/9"), vec![0, 2, 4, 7, 9]);
+    }
+
+    #[test]
+    fn test_slash_bass() {
+        assert_eq!(pitch_classes("C/E"), vec![0, 4, 7]);
+        assert_eq!(pitch_classes("Am/G"), vec![0, 4, 7, 9]);
+        assert_eq!(parse_chord("C/H"), Err(ChordError::InvalidBass));
+    }
+
+    #[test]
+    fn test_weights() {
+        let weights = ChordWeights {
+            root: 1.0,
+            third: 0.5,
+            fifth: 0.25,
+            extensions: 0.75,
+        };
+        let mask = parse_chord("Cmaj7").unwrap().mask(weights);
+        assert_eq!(mask[0], 1.0);
+        assert_eq!(mask[4], 0.5);
+        assert_eq!(mask[7], 0.25);
+        assert_eq!(mask[11], 0.75);
+        assert_eq!(mask[2], 0.0);
+    }
+
+    #[test]
+    fn test_invalid_symbols() {
+        assert_eq!(parse_chord(""), Err(ChordError::Empty));
+        assert_eq!(parse_chord("H7"), Err(ChordError::InvalidRoot));
+        assert_eq!(parse_chord("Cxyz"), Err(ChordError::UnknownSuffix));
+    }
+
+    #[test]
+    fn test_default_chords_parse() {
+        for symbol in DEFAULT_CHORDS {
+            assert!(parse_chord(symbol).is_ok(), "{symbol}");
+        }
+    }
+}
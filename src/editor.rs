@@ -0,0 +1,664 @@
+// Editor with a one-octave keyboard for the note gains and a plot of the bank's response
+// and the signals' spectra
+
+use atomic_float::AtomicF32;
+use nih_plug::prelude::*;
+use nih_plug_egui::egui::{
+    self, Align2, Color32, ComboBox, FontId, Pos2, Rect, Sense, Shape, Stroke, TextEdit, Ui, Vec2,
+};
+use nih_plug_egui::widgets::ParamSlider;
+use nih_plug_egui::{create_egui_editor, EguiState};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use triple_buffer::Output;
+
+use crate::analyzer::{Analysis, FLOOR_DB};
+use crate::frequency_list::{FrequencyList, FrequencyListError};
+use crate::response::{response_freq, Response, MAX_FREQ, MIN_FREQ};
+use crate::scales::{Root, Scale};
+use crate::wet::WetSnapshot;
+use crate::{ColourizerRsParams, TargetMode};
+
+/// Size of the editor window in logical pixels.
+const WIDTH: u32 = 560;
+const HEIGHT: u32 = 400;
+const KEYBOARD_HEIGHT: f32 = 140.0;
+/// Black keys are this fraction of a white key's width and height.
+const BLACK_KEY_WIDTH: f32 = 0.6;
+const BLACK_KEY_HEIGHT: f32 = 0.6;
+/// The notes of the white keys from left to right.
+const WHITE_KEYS: [usize; 7] = [0, 2, 4, 5, 7, 9, 11];
+const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+/// Level range of the response plot. The frequency range is that of the published response.
+const PLOT_MIN_DB: f32 = -40.0;
+const PLOT_MAX_DB: f32 = 24.0;
+/// Level range of the spectra drawn behind the response.
+const SPECTRUM_MIN_DB: f32 = -96.0;
+const SPECTRUM_MAX_DB: f32 = 0.0;
+/// The chroma meters show the pitch classes down to this many decibels below the loudest one.
+const CHROMA_RANGE_DB: f32 = 48.0;
+const CHROMA_METER_HEIGHT: f32 = 4.0;
+/// The gain reduction meter shows up to this many decibels, and falls back at this many
+/// decibels per second.
+const REDUCTION_RANGE_DB: f32 = 24.0;
+const REDUCTION_RELEASE_DB: f32 = 20.0;
+const REDUCTION_METER_WIDTH: f32 = 60.0;
+
+const ACCENT: Color32 = Color32::from_rgb(0xe0, 0x8a, 0x2c);
+const ERROR: Color32 = Color32::from_rgb(0xe0, 0x4a, 0x3a);
+
+/// The editor's initial size. The state is stored with the plugin so the window keeps its size.
+pub fn default_state() -> Arc<EguiState> {
+    EguiState::from_size(WIDTH, HEIGHT)
+}
+
+#[derive(Default)]
+struct EditorState {
+    /// The key that is being dragged to set its weight
+    dragged_key: Option<usize>,
+    /// The gain reduction shown by the meter, held and released slowly so peaks stay visible
+    reduction_db: f32,
+    /// The file the frequency list is loaded from
+    list_path: String,
+    /// The frequency list in its text form while it's being edited
+    list_text: String,
+    list_editor_open: bool,
+    /// The number of resonances set by the last load or edit, or why it failed
+    list_status: Option<Result<usize, FrequencyListError>>,
+    /// The response of the last published snapshot of the wet path
+    response: Option<Response>,
+}
+
+pub(crate) fn create(
+    params: Arc<ColourizerRsParams>,
+    editor_state: Arc<EguiState>,
+    response: Arc<Mutex<Output<WetSnapshot>>>,
+    analysis: Arc<Mutex<Output<Analysis>>>,
+    gain_reduction: Arc<AtomicF32>,
+) -> Option<Box<dyn Editor>> {
+    create_egui_editor(
+        editor_state,
+        EditorState::default(),
+        |_, _| {},
+        move |egui_ctx, setter, state| {
+            let mut analysis = analysis.lock().ok();
+            let analysis = analysis.as_mut().map(|output| output.read());
+            let dt = egui_ctx.input(|input| input.stable_dt);
+            state.reduction_db = release(
+                state.reduction_db,
+                gain_reduction.swap(0.0, Ordering::Relaxed),
+                dt,
+            );
+            egui::CentralPanel::default().show(egui_ctx, |ui| {
+                ui.horizontal(|ui| {
+                    if let Some(scale) = enum_picker(ui, "Scale", &params.scale, setter) {
+                        write_scale(&params, setter, scale, params.root.value());
+                    }
+                    if let Some(root) = enum_picker(ui, "Root", &params.root, setter) {
+                        write_scale(&params, setter, params.scale.value(), root);
+                    }
+                    ui.label("Input");
+                    ui.add(ParamSlider::for_param(&params.input_trim, setter).with_width(80.0));
+                    ui.label("Output");
+                    ui.add(ParamSlider::for_param(&params.output_gain, setter).with_width(80.0));
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Gain");
+                    ui.add(ParamSlider::for_param(&params.gain, setter).with_width(120.0));
+                    ui.label("Dry/Wet");
+                    ui.add(ParamSlider::for_param(&params.dry_wet, setter).with_width(120.0));
+                    enum_picker(ui, "Mix Law", &params.mix_law, setter);
+                    if params.limiter.value() {
+                        ui.label("GR");
+                        reduction_meter(ui, state.reduction_db);
+                    }
+                });
+                if params.target.value() == TargetMode::List {
+                    list_row(ui, &params, state);
+                }
+                if params.learn_tuning.value() || params.detected_reference().is_some() {
+                    tuning_row(ui, &params, setter);
+                }
+                ui.add_space(8.0);
+                keyboard(ui, &params, setter, state, analysis);
+                ui.add_space(8.0);
+                if let Ok(mut snapshot) = response.lock() {
+                    // The response is only evaluated when the audio thread published a change
+                    if snapshot.update() || state.response.is_none() {
+                        state
+                            .response
+                            .get_or_insert_with(Response::default)
+                            .compute(snapshot.output_buffer());
+                    }
+                }
+                if let Some(response) = &state.response {
+                    response_plot(ui, response, analysis);
+                }
+            });
+            list_editor(egui_ctx, &params, state);
+        },
+    )
+}
+
+/// Load the frequency list from a file or open the list editor, and show the result of the last
+/// attempt.
+fn list_row(ui: &mut Ui, params: &ColourizerRsParams, state: &mut EditorState) {
+    ui.horizontal(|ui| {
+        ui.label("List");
+        ui.add(
+            TextEdit::singleline(&mut state.list_path)
+                .hint_text("Path to a text or JSON file")
+                .desired_width(200.0),
+        );
+        if ui.button("Load").clicked() {
+            state.list_status = Some(store_list(params, FrequencyList::load(&state.list_path)));
+        }
+        if ui.button("Edit").clicked() {
+            if let Ok(list) = params.frequency_list.read() {
+                state.list_text = list.to_text();
+            }
+            state.list_editor_open = true;
+        }
+        match &state.list_status {
+            Some(Ok(count)) => {
+                ui.label(format!("{count} resonances"));
+            }
+            Some(Err(error)) => {
+                ui.colored_label(ERROR, error.to_string());
+            }
+            None => (),
+        }
+    });
+}
+
+/// The `Reference` slider next to the detected tuning, which can be copied to `Reference` so it
+/// stays in effect without `Apply Detected Tuning`.
+fn tuning_row(ui: &mut Ui, params: &ColourizerRsParams, setter: &ParamSetter) {
+    ui.horizontal(|ui| {
+        ui.label("Reference");
+        ui.add(ParamSlider::for_param(&params.reference, setter).with_width(120.0));
+        let detected = params.detected_reference();
+        ui.label(detected_label(detected));
+        if let Some(freq) = detected {
+            if ui.button("Apply to Reference").clicked() {
+                setter.begin_set_parameter(&params.reference);
+                setter.set_parameter(&params.reference, freq);
+                setter.end_set_parameter(&params.reference);
+            }
+        }
+    });
+}
+
+/// Describe the detected A4 as its offset from 440 Hz and its frequency.
+fn detected_label(detected: Option<f32>) -> String {
+    match detected {
+        Some(freq) => format!(
+            "Detected {:+.1} cents ({freq:.2} Hz)",
+            1200.0 * (freq / 440.0).log2()
+        ),
+        None => "Detecting...".to_string(),
+    }
+}
+
+/// A window for editing the frequency list in the text form. Applying keeps the window open so
+/// errors can be fixed in place.
+fn list_editor(ctx: &egui::Context, params: &ColourizerRsParams, state: &mut EditorState) {
+    let mut open = state.list_editor_open;
+    egui::Window::new("Frequency List")
+        .open(&mut open)
+        .show(ctx, |ui| {
+            ui.label("One resonance per line: frequency, gain and Q");
+            ui.add(
+                TextEdit::multiline(&mut state.list_text)
+                    .code_editor()
+                    .desired_rows(12),
+            );
+            if ui.button("Apply").clicked() {
+                state.list_status =
+                    Some(store_list(params, FrequencyList::parse(&state.list_text)));
+            }
+            if let Some(Err(error)) = &state.list_status {
+                ui.colored_label(ERROR, error.to_string());
+            }
+        });
+    state.list_editor_open = open;
+}
+
+/// Replace the stored frequency list if `list` was read successfully, and return its length.
+/// The audio thread picks the new list up with its next block.
+pub(crate) fn store_list(
+    params: &ColourizerRsParams,
+    list: Result<FrequencyList, FrequencyListError>,
+) -> Result<usize, FrequencyListError> {
+    let list = list?;
+    let count = list.resonances.len();
+    if let Ok(mut stored) = params.frequency_list.write() {
+        *stored = list;
+    }
+    Ok(count)
+}
+
+/// A combo box listing every variant of an enum parameter. Returns the variant that was picked,
+/// if any.
+fn enum_picker<T: Enum + PartialEq + Copy + Send + Sync + 'static>(
+    ui: &mut Ui,
+    label: &str,
+    param: &EnumParam<T>,
+    setter: &ParamSetter,
+) -> Option<T> {
+    let current = param.value().to_index();
+    let mut picked = None;
+    ComboBox::from_label(label)
+        .selected_text(T::variants()[current])
+        .show_ui(ui, |ui| {
+            for (i, name) in T::variants().iter().enumerate() {
+                if ui.selectable_label(i == current, *name).clicked() && i != current {
+                    let value = T::from_index(i);
+                    setter.begin_set_parameter(param);
+                    setter.set_parameter(param, value);
+                    setter.end_set_parameter(param);
+                    picked = Some(value);
+                }
+            }
+        });
+    picked
+}
+
+/// Write `scale` rotated to `root` to the twelve note parameters, so the notes stay the same
+/// when switching to `Custom` afterwards. `Custom` itself leaves them alone.
+fn write_scale(params: &ColourizerRsParams, setter: &ParamSetter, scale: Scale, root: Root) {
+    let Some(mask) = scale.mask(root) else {
+        return;
+    };
+    for (param, gain) in params.note_params().into_iter().zip(mask) {
+        setter.begin_set_parameter(param);
+        setter.set_parameter(param, gain);
+        setter.end_set_parameter(param);
+    }
+}
+
+/// Twelve keys showing the note gains. Clicking a key toggles the note and dragging sets its
+/// weight from the pointer's height. Editing a key while a scale preset is selected copies the
+/// preset to the note parameters and switches to `Custom`. The top of every key meters the
+/// pitch class in the output, with the input below it.
+fn keyboard(
+    ui: &mut Ui,
+    params: &ColourizerRsParams,
+    setter: &ParamSetter,
+    state: &mut EditorState,
+    analysis: Option<&Analysis>,
+) {
+    let (rect, response) = ui.allocate_exact_size(
+        Vec2::new(ui.available_width(), KEYBOARD_HEIGHT),
+        Sense::click_and_drag(),
+    );
+    let gains = params.note_param_gains();
+    let note_params = params.note_params();
+    let pointer = response.interact_pointer_pos();
+
+    if response.drag_started() {
+        state.dragged_key = pointer.and_then(|pos| key_at(rect, pos));
+        if let Some(note) = state.dragged_key {
+            unlock_notes(params, setter, &gains);
+            setter.begin_set_parameter(note_params[note]);
+        }
+    }
+    if let (Some(note), Some(pos)) = (state.dragged_key, pointer) {
+        let weight = weight_at(key_rect(rect, note), pos.y);
+        setter.set_parameter(note_params[note], weight);
+    }
+    if response.drag_stopped() {
+        if let Some(note) = state.dragged_key.take() {
+            setter.end_set_parameter(note_params[note]);
+        }
+    }
+    if response.clicked() {
+        if let Some(note) = pointer.and_then(|pos| key_at(rect, pos)) {
+            unlock_notes(params, setter, &gains);
+            let param = note_params[note];
+            setter.begin_set_parameter(param);
+            setter.set_parameter(param, if gains[note] > 0.0 { 0.0 } else { 1.0 });
+            setter.end_set_parameter(param);
+        }
+    }
+
+    // The white keys are drawn first so the black keys end up on top
+    let painter = ui.painter_at(rect);
+    let black_keys = (0..12).filter(|note| is_black(*note));
+    for note in WHITE_KEYS.into_iter().chain(black_keys) {
+        let key = key_rect(rect, note);
+        let (fill, text) = if is_black(note) {
+            (Color32::from_gray(40), Color32::from_gray(200))
+        } else {
+            (Color32::from_gray(230), Color32::from_gray(40))
+        };
+        painter.rect_filled(key.shrink(1.0), 2.0, fill);
+        let weight = gains[note].clamp(0.0, 1.0);
+        if weight > 0.0 {
+            let mut bar = key.shrink(3.0);
+            bar.set_top(bar.bottom() - weight * bar.height());
+            painter.rect_filled(bar, 2.0, ACCENT.gamma_multiply(0.4 + 0.6 * weight));
+        }
+        if let Some(analysis) = analysis {
+            let chromas = [
+                (&analysis.output_chroma, ACCENT),
+                (&analysis.input_chroma, Color32::from_gray(128)),
+            ];
+            for (row, (chroma, color)) in chromas.into_iter().enumerate() {
+                let loudest = chroma.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+                let mut meter = key.shrink(3.0);
+                meter.set_top(meter.top() + row as f32 * (CHROMA_METER_HEIGHT + 1.0));
+                meter.set_height(CHROMA_METER_HEIGHT);
+                meter.set_width(meter_level(chroma[note], loudest) * meter.width());
+                painter.rect_filled(meter, 0.0, color);
+            }
+        }
+        painter.text(
+            Pos2::new(key.center().x, key.bottom() - 10.0),
+            Align2::CENTER_CENTER,
+            NOTE_NAMES[note],
+            FontId::proportional(11.0),
+            text,
+        );
+    }
+}
+
+/// Make the note parameters match the selected scale preset and switch to `Custom`, so editing a
+/// key starts from what the keyboard shows.
+fn unlock_notes(params: &ColourizerRsParams, setter: &ParamSetter, gains: &[f32; 12]) {
+    if params.scale.value() == Scale::Custom {
+        return;
+    }
+    for (param, gain) in params.note_params().into_iter().zip(gains) {
+        setter.begin_set_parameter(param);
+        setter.set_parameter(param, *gain);
+        setter.end_set_parameter(param);
+    }
+    setter.begin_set_parameter(&params.scale);
+    setter.set_parameter(&params.scale, Scale::Custom);
+    setter.end_set_parameter(&params.scale);
+}
+
+/// The wet path's gain over frequency in front of the input and output spectra, with a grid
+/// line every octave of A and at 0 dB.
+fn response_plot(ui: &mut Ui, response: &Response, analysis: Option<&Analysis>) {
+    let (rect, _) = ui.allocate_exact_size(ui.available_size(), Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 2.0, Color32::from_gray(20));
+
+    let grid = Stroke::new(1.0, Color32::from_gray(50));
+    let mut freq = 27.5;
+    while freq < MAX_FREQ {
+        let x = freq_to_x(rect, freq);
+        painter.line_segment(
+            [Pos2::new(x, rect.top()), Pos2::new(x, rect.bottom())],
+            grid,
+        );
+        freq *= 2.0;
+    }
+    let zero = db_to_y(rect, 0.0);
+    painter.line_segment(
+        [Pos2::new(rect.left(), zero), Pos2::new(rect.right(), zero)],
+        grid,
+    );
+
+    if let Some(analysis) = analysis {
+        let spectra = [
+            (&analysis.input_spectrum, Color32::from_gray(90)),
+            (&analysis.output_spectrum, ACCENT.gamma_multiply(0.5)),
+        ];
+        for (spectrum, color) in spectra {
+            let points = spectrum
+                .iter()
+                .enumerate()
+                .map(|(k, db)| (analysis.bin_freq(k), db))
+                .filter(|(freq, _)| (MIN_FREQ..MAX_FREQ).contains(freq))
+                .map(|(freq, db)| Pos2::new(freq_to_x(rect, freq), spectrum_to_y(rect, *db)))
+                .collect();
+            painter.add(Shape::line(points, Stroke::new(1.0, color)));
+        }
+    }
+
+    let points = response
+        .magnitude_db
+        .iter()
+        .enumerate()
+        .map(|(i, db)| Pos2::new(freq_to_x(rect, response_freq(i)), db_to_y(rect, *db)))
+        .collect();
+    painter.add(Shape::line(points, Stroke::new(1.5, ACCENT)));
+}
+
+/// A bar that fills from the left with the limiter's gain reduction.
+fn reduction_meter(ui: &mut Ui, reduction_db: f32) {
+    let height = ui.spacing().interact_size.y;
+    let (rect, _) =
+        ui.allocate_exact_size(Vec2::new(REDUCTION_METER_WIDTH, height), Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 2.0, Color32::from_gray(20));
+    let mut bar = rect;
+    bar.set_width(reduction_level(reduction_db) * rect.width());
+    painter.rect_filled(bar, 2.0, ACCENT);
+    painter.text(
+        rect.center(),
+        Align2::CENTER_CENTER,
+        format!("{reduction_db:.1} dB"),
+        FontId::proportional(10.0),
+        Color32::WHITE,
+    );
+}
+
+/// Whether a pitch class is played on a black key.
+fn is_black(note: usize) -> bool {
+    matches!(note % 12, 1 | 3 | 6 | 8 | 10)
+}
+
+/// The area of a key on a keyboard occupying `keyboard`. White keys span the full height and
+/// black keys sit on the border between two white keys.
+pub fn key_rect(keyboard: Rect, note: usize) -> Rect {
+    let white_width = keyboard.width() / WHITE_KEYS.len() as f32;
+    // Black keys sit to the right of the white key below them
+    let white_index = WHITE_KEYS.iter().filter(|white| **white <= note).count() - 1;
+    let left = keyboard.left() + white_index as f32 * white_width;
+    if is_black(note) {
+        let width = BLACK_KEY_WIDTH * white_width;
+        Rect::from_min_size(
+            Pos2::new(left + white_width - width / 2.0, keyboard.top()),
+            Vec2::new(width, BLACK_KEY_HEIGHT * keyboard.height()),
+        )
+    } else {
+        Rect::from_min_size(
+            Pos2::new(left, keyboard.top()),
+            Vec2::new(white_width, keyboard.height()),
+        )
+    }
+}
+
+/// The key under a position. Black keys take precedence because they're drawn on top.
+pub fn key_at(keyboard: Rect, pos: Pos2) -> Option<usize> {
+    if !keyboard.contains(pos) {
+        return None;
+    }
+    (0..12)
+        .filter(|note| is_black(*note))
+        .chain(WHITE_KEYS)
+        .find(|note| key_rect(keyboard, *note).contains(pos))
+}
+
+/// The weight set by dragging to height `y` on a key: 0 at the top and 1 at the bottom.
+pub fn weight_at(key: Rect, y: f32) -> f32 {
+    ((y - key.top()) / key.height()).clamp(0.0, 1.0)
+}
+
+/// Horizontal position of a frequency on the plot's logarithmic axis.
+pub fn freq_to_x(plot: Rect, freq: f32) -> f32 {
+    let t = (freq / MIN_FREQ).ln() / (MAX_FREQ / MIN_FREQ).ln();
+    plot.left() + t * plot.width()
+}
+
+pub fn x_to_freq(plot: Rect, x: f32) -> f32 {
+    let t = (x - plot.left()) / plot.width();
+    MIN_FREQ * (MAX_FREQ / MIN_FREQ).powf(t)
+}
+
+/// Vertical position of a level in decibels. Levels outside the plot's range are clamped to its
+/// edges.
+pub fn db_to_y(plot: Rect, db: f32) -> f32 {
+    let t = (db.clamp(PLOT_MIN_DB, PLOT_MAX_DB) - PLOT_MIN_DB) / (PLOT_MAX_DB - PLOT_MIN_DB);
+    plot.bottom() - t * plot.height()
+}
+
+/// Vertical position of a spectrum level in dBFS on its own axis.
+pub fn spectrum_to_y(plot: Rect, db: f32) -> f32 {
+    let t = (db.clamp(SPECTRUM_MIN_DB, SPECTRUM_MAX_DB) - SPECTRUM_MIN_DB)
+        / (SPECTRUM_MAX_DB - SPECTRUM_MIN_DB);
+    plot.bottom() - t * plot.height()
+}
+
+/// How far the gain reduction meter is filled, from 0 without reduction to 1 at
+/// `REDUCTION_RANGE_DB`.
+pub fn reduction_level(reduction_db: f32) -> f32 {
+    (reduction_db / REDUCTION_RANGE_DB).clamp(0.0, 1.0)
+}
+
+/// The meter's new reading after `dt` seconds: it jumps up to `peak_db` and falls back at
+/// `REDUCTION_RELEASE_DB` per second.
+pub fn release(shown_db: f32, peak_db: f32, dt: f32) -> f32 {
+    peak_db.max(shown_db - REDUCTION_RELEASE_DB * dt).max(0.0)
+}
+
+/// How far a chroma meter is filled, from 0 at `CHROMA_RANGE_DB` below the loudest pitch class
+/// to 1 at the loudest one. Silent pitch classes stay empty.
+pub fn meter_level(db: f32, loudest: f32) -> f32 {
+    if db <= FLOOR_DB {
+        return 0.0;
+    }
+    (1.0 + (db - loudest) / CHROMA_RANGE_DB).clamp(0.0, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyboard() -> Rect {
+        Rect::from_min_size(Pos2::new(10.0, 20.0), Vec2::new(700.0, 140.0))
+    }
+
+    #[test]
+    fn test_white_keys_tile_the_keyboard() {
+        let keyboard = keyboard();
+        let mut left = keyboard.left();
+        for note in WHITE_KEYS {
+            let key = key_rect(keyboard, note);
+            assert!((key.left() - left).abs() < 1e-3, "{note}");
+            assert_eq!(key.height(), keyboard.height());
+            left = key.right();
+        }
+        assert!((left - keyboard.right()).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_every_key_can_be_hit() {
+        let keyboard = keyboard();
+        for (note, name) in NOTE_NAMES.iter().enumerate() {
+            let key = key_rect(keyboard, note);
+            // The lower half of a white key is never covered by a black key
+            let pos = Pos2::new(key.center().x, key.bottom() - 1.0);
+            assert_eq!(key_at(keyboard, pos), Some(note), "{name}");
+        }
+    }
+
+    #[test]
+    fn test_black_keys_cover_white_keys() {
+        let keyboard = keyboard();
+        let c_sharp = key_rect(keyboard, 1);
+        // C# straddles C and D
+        assert!(c_sharp.left() < key_rect(keyboard, 0).right());
+        assert!(c_sharp.right() > key_rect(keyboard, 2).left());
+        assert_eq!(key_at(keyboard, c_sharp.center()), Some(1));
+        // There's no black key between E and F
+        let e = key_rect(keyboard, 4);
+        assert_eq!(
+            key_at(keyboard, Pos2::new(e.right() - 1.0, e.top() + 1.0)),
+            Some(4)
+        );
+        assert_eq!(key_at(keyboard, Pos2::new(0.0, 0.0)), None);
+    }
+
+    #[test]
+    fn test_weight_follows_drag_height() {
+        let key = key_rect(keyboard(), 0);
+        assert_eq!(weight_at(key, key.top()), 0.0);
+        assert_eq!(weight_at(key, key.bottom()), 1.0);
+        assert_eq!(weight_at(key, key.center().y), 0.5);
+        assert_eq!(weight_at(key, key.bottom() + 50.0), 1.0);
+    }
+
+    #[test]
+    fn test_plot_axes() {
+        let plot = Rect::from_min_size(Pos2::new(0.0, 200.0), Vec2::new(500.0, 160.0));
+        assert_eq!(freq_to_x(plot, MIN_FREQ), plot.left());
+        assert!((freq_to_x(plot, MAX_FREQ) - plot.right()).abs() < 1e-3);
+        for freq in [55.0, 440.0, 7040.0] {
+            let x = freq_to_x(plot, freq);
+            assert!((x_to_freq(plot, x) - freq).abs() < 1e-3 * freq);
+        }
+        assert_eq!(db_to_y(plot, PLOT_MAX_DB), plot.top());
+        assert_eq!(db_to_y(plot, -100.0), plot.bottom());
+    }
+
+    #[test]
+    fn test_detected_label() {
+        assert_eq!(detected_label(None), "Detecting...");
+        assert_eq!(
+            detected_label(Some(440.0)),
+            "Detected +0.0 cents (440.00 Hz)"
+        );
+        assert_eq!(
+            detected_label(Some(432.0)),
+            "Detected -31.8 cents (432.00 Hz)"
+        );
+    }
+
+    #[test]
+    fn test_meters() {
+        let plot = Rect::from_min_size(Pos2::new(0.0, 200.0), Vec2::new(500.0, 160.0));
+        assert_eq!(spectrum_to_y(plot, 0.0), plot.top());
+        assert_eq!(spectrum_to_y(plot, -120.0), plot.bottom());
+        assert_eq!(meter_level(-12.0, -12.0), 1.0);
+        assert_eq!(meter_level(-36.0, -12.0), 0.5);
+        assert_eq!(meter_level(-100.0, -12.0), 0.0);
+        assert_eq!(meter_level(FLOOR_DB, FLOOR_DB), 0.0);
+    }
+
+    #[test]
+    fn test_store_list() {
+        let params = ColourizerRsParams::default();
+        assert_eq!(
+            store_list(&params, FrequencyList::parse("100\n200 0.5")),
+            Ok(2)
+        );
+        assert_eq!(params.frequency_list.read().unwrap().resonances.len(), 2);
+
+        // A list that doesn't parse leaves the stored one alone
+        let error = store_list(&params, FrequencyList::parse("100\n-5")).unwrap_err();
+        assert_eq!(error, FrequencyListError::InvalidValue { entry: 2 });
+        assert_eq!(params.frequency_list.read().unwrap().resonances.len(), 2);
+        assert!(matches!(
+            store_list(&params, FrequencyList::load("/nonexistent/list.txt")),
+            Err(FrequencyListError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_reduction_meter() {
+        assert_eq!(reduction_level(0.0), 0.0);
+        assert_eq!(reduction_level(12.0), 0.5);
+        assert_eq!(reduction_level(48.0), 1.0);
+        // Peaks show immediately and fall back slowly
+        assert_eq!(release(0.0, 6.0, 0.1), 6.0);
+        assert!((release(6.0, 0.0, 0.1) - 4.0).abs() < 1e-6);
+        assert_eq!(release(1.0, 0.0, 1.0), 0.0);
+    }
+}
@@ -1,8 +1,13 @@
 // Filter bank for pitchmap-like effect
 
+use realfft::num_complex::{Complex32, Complex64};
+
+use crate::frequency_list::{Resonance, MAX_RESONANCES};
+use crate::tuning::Tuning;
+
 /// Peaking biquad filter used to construct narrow band-pass responses.
 /// A high positive gain combined with a large `Q` yields a sharp peak.
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Default)]
 struct PeakFilter {
     b0: f32,
     b1: f32,
@@ -38,6 +43,54 @@ impl PeakFilter {
         }
     }
 
+    /// Create a peaking filter whose `Q` is chosen so the resonance decays by 60 dB in
+    /// `decay` seconds.
+    fn with_decay(freq: f32, decay: f32, gain_db: f32, sample_rate: f32) -> Self {
+        let a = 10.0_f32.powf(gain_db / 40.0);
+        let w0 = 2.0 * std::f32::consts::PI * freq / sample_rate;
+        // The poles have radius sqrt(a2), and r^(decay * sample_rate) should equal -60 dB
+        let a2 = 10.0_f32.powf(-6.0 / (decay * sample_rate));
+        let alpha = a * (1.0 - a2) / (1.0 + a2);
+        let q = w0.sin() / (2.0 * alpha);
+        Self::new(freq, q, gain_db, sample_rate)
+    }
+
+    /// Replace the coefficients with `other`'s while keeping this filter's state.
+    fn retune(&mut self, other: PeakFilter) {
+        *self = Self {
+            z1: self.z1,
+            z2: self.z2,
+            ..other
+        };
+    }
+
+    /// Move the poles onto the unit circle so the current resonance rings forever.
+    fn freeze(&mut self, freq: f32, sample_rate: f32) {
+        let w0 = 2.0 * std::f32::consts::PI * freq / sample_rate;
+        self.a1 = -2.0 * w0.cos();
+        self.a2 = 1.0;
+    }
+
+    /// Scale the state down when the output exceeds `limit` so long decays and frozen
+    /// resonances can't run away.
+    fn limit(&mut self, out: f32, limit: f32) {
+        if !out.is_finite() {
+            self.z1 = 0.0;
+            self.z2 = 0.0;
+        } else if out.abs() > limit {
+            let scale = limit / out.abs();
+            self.z1 *= scale;
+            self.z2 *= scale;
+        }
+    }
+
+    /// The filter's transfer function at `z^-1` and `z^-2`. This is evaluated in double
+    /// precision because the denominator nearly cancels out close to the resonance.
+    fn response(&self, z1: Complex64, z2: Complex64) -> Complex64 {
+        let [b0, b1, b2, a1, a2] = [self.b0, self.b1, self.b2, self.a1, self.a2].map(f64::from);
+        (b0 + z1 * b1 + z2 * b2) / (1.0 + z1 * a1 + z2 * a2)
+    }
+
     /// Process a single sample through the filter.
     fn process(&mut self, input: f32) -> f32 {
         let out = self.b0 * input + self.z1;
@@ -45,6 +98,50 @@ impl PeakFilter {
         self.z2 = self.b2 * input - self.a2 * out;
         out
     }
+
+    /// `Q` of the band-pass component, the peak minus the input. Its poles are those of the
+    /// peak, so this is the peak's `Q` times its linear gain.
+    fn band_q(&self, freq: f32, sample_rate: f32) -> f32 {
+        let w0 = 2.0 * std::f32::consts::PI * freq / sample_rate;
+        let alpha = (1.0 - self.a2) / (1.0 + self.a2);
+        w0.sin() / (2.0 * alpha)
+    }
+}
+
+/// Second-order all-pass filter. Its phase falls from 0 to -360 degrees, passing -180 degrees
+/// at the centre frequency.
+#[derive(Clone, Copy)]
+struct AllPass {
+    a1: f32,
+    a2: f32,
+    z1: f32,
+    z2: f32,
+}
+
+impl AllPass {
+    fn new(freq: f32, q: f32, sample_rate: f32) -> Self {
+        let w0 = 2.0 * std::f32::consts::PI * freq / sample_rate;
+        let alpha = w0.sin() / (2.0 * q);
+        let a0 = 1.0 + alpha;
+        Self {
+            a1: -2.0 * w0.cos() / a0,
+            a2: (1.0 - alpha) / a0,
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+
+    fn response(&self, z1: Complex64, z2: Complex64) -> Complex64 {
+        let [a1, a2] = [self.a1, self.a2].map(f64::from);
+        (a2 + z1 * a1 + z2) / (1.0 + z1 * a1 + z2 * a2)
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let out = self.a2 * input + self.z1;
+        self.z1 = self.a1 * (input - out) + self.z2;
+        self.z2 = input - self.a2 * out;
+        out
+    }
 }
 
 /// Convert a note name to a semitone index from C.
@@ -67,17 +164,138 @@ fn note_index(name: &str) -> Option<u8> {
     }
 }
 
+/// `Q` of the peaking filters when no decay time is set.
+pub const DEFAULT_Q: f32 = 100.0;
+/// `Q` of the hum series' filters when no decay time is set. This is lower than for the notes
+/// so the notches settle within a fraction of a second and tolerate some drift of the mains
+/// frequency.
+const HUM_Q: f32 = 20.0;
+/// Peak gain of the peaking filters in decibels.
+const PEAK_GAIN_DB: f32 = 20.0;
+/// Gain of the band-pass component at a peak's centre, `10^(PEAK_GAIN_DB / 20) - 1`.
+const PEAK_BAND_GAIN: f32 = 9.0;
+/// Maximum output of a single filter before its state is scaled down.
+const FILTER_LIMIT: f32 = 32.0;
+/// Number of notes in the bank, C0 to B8.
+const NUM_NOTES: usize = 108;
+/// The odd partials that can be added for every note.
+const PARTIALS: [f32; 3] = [3.0, 5.0, 7.0];
+/// Partials above this fraction of the sample rate are left out.
+const MAX_PARTIAL_RATIO: f32 = 0.45;
+/// The most harmonics a hum series can have, including the fundamental.
+pub const MAX_HUM_HARMONICS: usize = 32;
+/// The most filters the bank can run at once, for any target.
+const MAX_FILTERS: usize = max_usize(
+    NUM_NOTES * (1 + PARTIALS.len()),
+    max_usize(MAX_HUM_HARMONICS, MAX_RESONANCES),
+);
+
+const fn max_usize(a: usize, b: usize) -> usize {
+    if a > b {
+        a
+    } else {
+        b
+    }
+}
+
+/// The highest centre frequency the bank uses at `sample_rate`. Filters above it are left out.
+pub fn max_frequency(sample_rate: f32) -> f32 {
+    MAX_PARTIAL_RATIO * sample_rate
+}
+
+/// Settings for the harmonic partials added on top of every note.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Harmonics {
+    /// How many of the 3rd, 5th and 7th harmonics to add, from 0 to 3.
+    pub count: usize,
+    /// Gain of each partial relative to the previous one.
+    pub rolloff: f32,
+    /// Stiff-string inharmonicity coefficient `B`. Partial `n` lies at `n * f * sqrt(1 + B * n^2)`.
+    pub inharmonicity: f32,
+}
+
+/// What the filters of the bank are centred on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Target {
+    /// Every note from C0 to B8, weighted by the note gains
+    Notes,
+    /// A harmonic series
+    Hum(Hum),
+    /// The resonances set with `FilterBank::set_frequency_list()`
+    List,
+}
+
+/// A fundamental and its harmonics that replace the note grid, for example mains hum.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hum {
+    /// Frequency of the fundamental in Hz.
+    pub fundamental: f32,
+    /// Number of filters including the fundamental, up to `MAX_HUM_HARMONICS`.
+    pub harmonics: usize,
+}
+
+impl Default for Harmonics {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            rolloff: 0.5,
+            inharmonicity: 0.0,
+        }
+    }
+}
+
+/// A filter in the bank together with the pitch class it belongs to and its weight relative to
+/// that pitch class' gain.
+struct BankFilter {
+    idx: u8,
+    midi: u8,
+    freq: f32,
+    /// 0 for the fundamental, 1 and up for the 3rd, 5th and 7th harmonics
+    order: i32,
+    weight: f32,
+    /// `Q` used when no decay time is set
+    q: f32,
+    filter: PeakFilter,
+}
+
+impl BankFilter {
+    /// The filter's coefficients for `decay`, before freezing.
+    fn design(&self, decay: Option<f32>, sample_rate: f32) -> PeakFilter {
+        match decay {
+            Some(decay) => PeakFilter::with_decay(self.freq, decay, PEAK_GAIN_DB, sample_rate),
+            None => PeakFilter::new(self.freq, self.q, PEAK_GAIN_DB, sample_rate),
+        }
+    }
+}
+
 /// Filter bank with a peaking filter for each note from C0 to B8.
 pub struct FilterBank {
-    filters: Vec<(u8, PeakFilter)>,
+    filters: Vec<BankFilter>,
     gains: [f32; 12],
+    sample_rate: f32,
+    /// Time in seconds for the resonances to decay by 60 dB, or `None` for the default `Q`
+    decay: Option<f32>,
+    /// Whether the input is muted and the current resonances keep ringing
+    frozen: bool,
+    /// Whether the enabled notes are cut from the input instead of picked out
+    inverted: bool,
+    harmonics: Harmonics,
+    tuning: Tuning,
+    /// The note filters are only used when targeting the notes, otherwise `custom_filters` are
+    target: Target,
+    custom_filters: Vec<BankFilter>,
+    /// The resonances used when targeting the frequency list
+    list: Vec<Resonance>,
+    /// Counts the changes to `list`
+    list_generation: u32,
 }
 
 impl FilterBank {
     /// Create a new filter bank. The provided scale lists the note names that
     /// should be audible.
     pub fn new(sample_rate: f32) -> Self {
-        let mut filters = Vec::new();
+        // Room for all partials so enabling them doesn't allocate on the audio thread
+        let mut filters = Vec::with_capacity(NUM_NOTES * (1 + PARTIALS.len()));
 
         // Piano range C0 (midi 12) .. B8 (midi 119)
         for midi in 12u8..=119u8 {
@@ -87,14 +305,273 @@ impl FilterBank {
             // The original version used Q=300 and 40 dB gain which produced
             // very sharp peaks and extreme amplification. Here the Q and gain
             // are reduced to keep the effect more controlled.
-            let filter = PeakFilter::new(freq, 100.0, 20.0, sample_rate);
-            filters.push((idx, filter));
+            let filter = PeakFilter::new(freq, DEFAULT_Q, PEAK_GAIN_DB, sample_rate);
+            filters.push(BankFilter {
+                idx,
+                midi,
+                freq,
+                order: 0,
+                weight: 1.0,
+                q: DEFAULT_Q,
+                filter,
+            });
         }
 
         Self {
             filters,
             gains: [1.0; 12],
+            sample_rate,
+            decay: None,
+            frozen: false,
+            inverted: false,
+            harmonics: Harmonics::default(),
+            tuning: Tuning::default(),
+            target: Target::Notes,
+            custom_filters: Vec::with_capacity(MAX_HUM_HARMONICS.max(MAX_RESONANCES)),
+            list: Vec::with_capacity(MAX_RESONANCES),
+            list_generation: 0,
+        }
+    }
+
+    /// The centre frequency of a note's fundamental (`order` 0) or one of its harmonics.
+    fn filter_freq(&self, midi: u8, order: i32) -> f32 {
+        let semitones = midi as f32 - 69.0 + self.tuning.cents(midi) / 100.0;
+        let fundamental = self.tuning.reference * 2.0_f32.powf(semitones / 12.0);
+        if order == 0 {
+            return fundamental;
+        }
+        let n = PARTIALS[order as usize - 1];
+        n * fundamental * (1.0 + self.harmonics.inharmonicity * n * n).sqrt()
+    }
+
+    /// Offset the centre frequencies by per-note cents and stretched tuning so the peaks line
+    /// up with real-world partials. The filter states are kept.
+    pub fn set_tuning(&mut self, tuning: Tuning) {
+        if tuning == self.tuning {
+            return;
+        }
+        self.tuning = tuning;
+        for i in 0..self.filters.len() {
+            self.filters[i].freq = self.filter_freq(self.filters[i].midi, self.filters[i].order);
+        }
+        self.update_coefficients();
+    }
+
+    /// Add filters for the 3rd, 5th and 7th harmonics of every note so enabled pitch classes
+    /// excite a string-like series of partials instead of only octaves. The filters keep their
+    /// state while the rolloff and inharmonicity change, and are only recreated when the count
+    /// changes or the inharmonicity moves a partial past `MAX_PARTIAL_RATIO`.
+    pub fn set_harmonics(&mut self, harmonics: Harmonics) {
+        let harmonics = Harmonics {
+            count: harmonics.count.min(PARTIALS.len()),
+            ..harmonics
+        };
+        if harmonics == self.harmonics {
+            return;
+        }
+        let count_changed = harmonics.count != self.harmonics.count;
+        let retune = harmonics.inharmonicity != self.harmonics.inharmonicity;
+        self.harmonics = harmonics;
+
+        if count_changed || self.partial_count() != self.filters.len() - NUM_NOTES {
+            self.push_partials();
+            self.update_coefficients();
+            return;
+        }
+
+        for f in &mut self.filters[NUM_NOTES..] {
+            f.weight = harmonics.rolloff.powi(f.order);
+        }
+        if retune {
+            for i in NUM_NOTES..self.filters.len() {
+                self.filters[i].freq =
+                    self.filter_freq(self.filters[i].midi, self.filters[i].order);
+            }
+            self.update_coefficients();
+        }
+    }
+
+    /// The number of partials `push_partials()` adds for the current settings. The partials
+    /// of every note stop at the first one above `MAX_PARTIAL_RATIO`.
+    fn partial_count(&self) -> usize {
+        self.filters[..NUM_NOTES]
+            .iter()
+            .map(|f| {
+                (1..=self.harmonics.count as i32)
+                    .take_while(|order| {
+                        self.filter_freq(f.midi, *order) < MAX_PARTIAL_RATIO * self.sample_rate
+                    })
+                    .count()
+            })
+            .sum()
+    }
+
+    /// Recreate the partials' filters for the current harmonics settings and sample rate.
+    fn push_partials(&mut self) {
+        let harmonics = self.harmonics;
+        self.filters.truncate(NUM_NOTES);
+        for note in 0..NUM_NOTES {
+            let idx = self.filters[note].idx;
+            let midi = self.filters[note].midi;
+            for order in 1..=harmonics.count as i32 {
+                let freq = self.filter_freq(midi, order);
+                if freq >= MAX_PARTIAL_RATIO * self.sample_rate {
+                    break;
+                }
+                self.filters.push(BankFilter {
+                    idx,
+                    midi,
+                    freq,
+                    order,
+                    weight: harmonics.rolloff.powi(order),
+                    q: DEFAULT_Q,
+                    filter: PeakFilter::new(freq, DEFAULT_Q, PEAK_GAIN_DB, self.sample_rate),
+                });
+            }
+        }
+    }
+
+    /// Centre the filters on the notes, a harmonic series or the frequency list. The hum
+    /// series and the list ignore the note gains. Each of their filters is assigned to the
+    /// pitch class nearest to its frequency.
+    pub fn set_target(&mut self, target: Target) {
+        let target = match target {
+            Target::Hum(hum) => Target::Hum(Hum {
+                harmonics: hum.harmonics.clamp(1, MAX_HUM_HARMONICS),
+                ..hum
+            }),
+            target => target,
+        };
+        if target == self.target {
+            return;
+        }
+        self.target = target;
+        self.push_custom();
+        self.update_coefficients();
+    }
+
+    /// Replace the resonances used when targeting the list. Entries past `MAX_RESONANCES`,
+    /// above `max_frequency()` or with invalid values are left out. This doesn't allocate.
+    pub fn set_frequency_list(&mut self, resonances: &[Resonance]) {
+        let resonances = &resonances[..resonances.len().min(MAX_RESONANCES)];
+        if resonances == self.list.as_slice() {
+            return;
+        }
+        self.list.clear();
+        self.list.extend_from_slice(resonances);
+        self.list_generation = self.list_generation.wrapping_add(1);
+        if self.target == Target::List {
+            self.push_custom();
+            self.update_coefficients();
+        }
+    }
+
+    /// Changes every time `set_frequency_list()` replaces the list, so callers can tell when
+    /// anything derived from it is stale.
+    pub fn list_generation(&self) -> u32 {
+        self.list_generation
+    }
+
+    /// Recreate the filters of the hum series or the list for the current settings and sample
+    /// rate.
+    fn push_custom(&mut self) {
+        self.custom_filters.clear();
+        let max = max_frequency(self.sample_rate);
+        let reference = self.tuning.reference;
+        let filter = |freq: f32, weight: f32, q: f32, sample_rate: f32| {
+            let midi = (69.0 + 12.0 * (freq / reference).log2())
+                .round()
+                .clamp(0.0, 127.0) as u8;
+            BankFilter {
+                idx: midi % 12,
+                midi,
+                freq,
+                order: 0,
+                weight,
+                q,
+                filter: PeakFilter::new(freq, q, PEAK_GAIN_DB, sample_rate),
+            }
+        };
+        match self.target {
+            Target::Notes => (),
+            Target::Hum(hum) => {
+                for n in 1..=hum.harmonics {
+                    let freq = n as f32 * hum.fundamental;
+                    if freq >= max {
+                        break;
+                    }
+                    self.custom_filters
+                        .push(filter(freq, 1.0, HUM_Q, self.sample_rate));
+                }
+            }
+            Target::List => {
+                // Lists restored from the plugin state haven't been through `parse()`
+                for resonance in self.list.iter().filter(|r| r.is_valid() && r.freq < max) {
+                    self.custom_filters.push(filter(
+                        resonance.freq,
+                        resonance.gain,
+                        resonance.q,
+                        self.sample_rate,
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Change the sample rate the bank runs at, for example when it's oversampled. This clears
+    /// the filters' states but doesn't allocate.
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        if sample_rate == self.sample_rate {
+            return;
+        }
+        self.sample_rate = sample_rate;
+        self.push_partials();
+        self.push_custom();
+        for f in &mut self.filters {
+            f.filter = PeakFilter::new(f.freq, f.q, PEAK_GAIN_DB, sample_rate);
+        }
+        self.update_coefficients();
+    }
+
+    /// Set the time in seconds the resonances take to decay by 60 dB. `None` restores the
+    /// default `Q`. The filter states are kept so this can be changed while processing.
+    pub fn set_decay(&mut self, decay: Option<f32>) {
+        let decay = decay.filter(|d| *d > 0.0);
+        if decay == self.decay {
+            return;
         }
+        self.decay = decay;
+        self.update_coefficients();
+    }
+
+    /// Freeze the bank. While frozen the input is ignored and the excited resonances keep
+    /// ringing until the bank is unfrozen.
+    pub fn set_freeze(&mut self, frozen: bool) {
+        if frozen == self.frozen {
+            return;
+        }
+        self.frozen = frozen;
+        self.update_coefficients();
+    }
+
+    fn update_coefficients(&mut self) {
+        for f in self
+            .filters
+            .iter_mut()
+            .chain(self.custom_filters.iter_mut())
+        {
+            let filter = f.design(self.decay, self.sample_rate);
+            f.filter.retune(filter);
+            if self.frozen {
+                f.filter.freeze(f.freq, self.sample_rate);
+            }
+        }
+    }
+
+    /// Cut the enabled notes from the input and pass everything else. A gain of 1 notches a
+    /// note out completely.
+    pub fn set_invert(&mut self, inverted: bool) {
+        self.inverted = inverted;
     }
 
     /// Update the per-note gains. Expects an array of 12 values for C..B.
@@ -102,23 +579,247 @@ impl FilterBank {
         self.gains = gains;
     }
 
+    /// The response of the current configuration at `freq` Hz, computed from the filters'
+    /// coefficients. The magnitude is the gain applied to a sine at that frequency and the
+    /// argument is its phase shift in radians. The state limiting of long decays isn't modelled.
+    pub fn response(&self, freq: f32) -> Complex32 {
+        let (filters, gains) = self.active_filters();
+        let peaks = filters
+            .iter()
+            .map(|f| (f.filter, gains[f.idx as usize] * f.weight));
+        bank_response(freq, self.sample_rate, peaks, self.frozen, self.inverted)
+    }
+
+    /// Copy everything `response()` needs to `snapshot`, so the response can be evaluated on
+    /// another thread. This doesn't allocate.
+    pub fn snapshot(&self, snapshot: &mut BankSnapshot) {
+        let (filters, gains) = self.active_filters();
+        snapshot.sample_rate = self.sample_rate;
+        snapshot.frozen = self.frozen;
+        snapshot.inverted = self.inverted;
+        snapshot.len = filters.len().min(MAX_FILTERS);
+        for (peak, f) in snapshot.peaks.iter_mut().zip(filters) {
+            *peak = (f.filter, gains[f.idx as usize] * f.weight);
+        }
+    }
+
+    /// The filters of the current target and the note gains that apply to them.
+    fn active_filters(&self) -> (&[BankFilter], [f32; 12]) {
+        match self.target {
+            Target::Notes => (&self.filters, self.gains),
+            Target::Hum(_) | Target::List => (&self.custom_filters, [1.0; 12]),
+        }
+    }
+
+    /// Fill `compensation` with an all-pass for every audible filter of the current target and
+    /// flip its polarity, so the dry signal follows the phase of the peaks. Each all-pass
+    /// is centred on its filter's frequency with twice the bandwidth of the band-pass
+    /// component, which keeps it within a few degrees of the peak over the peak's bandwidth.
+    /// Filters whose note is disabled are skipped, so the compensation follows the gains at
+    /// the time of the call. Inverting leaves the compensation empty. This doesn't allocate.
+    pub fn update_phase_compensation(&self, compensation: &mut PhaseCompensation) {
+        if self.inverted {
+            compensation.clear();
+            return;
+        }
+        let (filters, gains) = self.active_filters();
+        compensation.flipped = true;
+        let sample_rate = compensation.sample_rate;
+        let max = max_frequency(sample_rate);
+        let mut count = 0;
+        for f in filters
+            .iter()
+            .filter(|f| f.freq < max && gains[f.idx as usize] * f.weight != 0.0)
+        {
+            let q = f
+                .design(self.decay, self.sample_rate)
+                .band_q(f.freq, self.sample_rate);
+            let section = AllPass::new(f.freq, q / 2.0, sample_rate);
+            match compensation.sections.get_mut(count) {
+                Some(current) => {
+                    current.a1 = section.a1;
+                    current.a2 = section.a2;
+                }
+                None => compensation.sections.push(section),
+            }
+            count += 1;
+        }
+        compensation.sections.truncate(count);
+    }
+
     /// Process a single sample through the filter bank.
     pub fn process_sample(&mut self, input: f32) -> f32 {
-        let mut sum = 0.0;
-        let mut gain_sum = 0.0;
-        for (idx, filter) in &mut self.filters {
-            let g = self.gains[*idx as usize];
-            let out = filter.process(input);
-            sum += out * g;
-            gain_sum += g;
+        let mut stems = [0.0; 12];
+        self.process_sample_split(input, &mut stems)
+    }
+
+    /// Process a single sample and write every pitch class' share of the output to `stems`.
+    /// The stems add up to the output. In invert mode they hold what is cut from each pitch
+    /// class instead, so the output is the input plus the stems.
+    pub fn process_sample_split(&mut self, input: f32, stems: &mut [f32; 12]) -> f32 {
+        let dry = input;
+        let input = if self.frozen { 0.0 } else { input };
+        // Only long decays and frozen resonances can run away. Without them the bank stays
+        // linear at any input level.
+        let limited = self.decay.is_some() || self.frozen;
+        *stems = [0.0; 12];
+        let (filters, gains) = match self.target {
+            Target::Notes => (&mut self.filters, self.gains),
+            Target::Hum(_) | Target::List => (&mut self.custom_filters, [1.0; 12]),
+        };
+        for f in filters {
+            let g = gains[f.idx as usize] * f.weight;
+            let out = f.filter.process(input);
+            if limited {
+                f.filter.limit(out, FILTER_LIMIT);
+            }
+            stems[f.idx as usize] += (out - input) * g;
+        }
+        if self.inverted {
+            // The band-pass component has a gain of `PEAK_BAND_GAIN` at the peaks' centres,
+            // so subtracting it scaled down leaves a notch
+            for stem in stems.iter_mut() {
+                *stem /= -PEAK_BAND_GAIN;
+            }
+            dry + stems.iter().sum::<f32>()
+        } else {
+            stems.iter().sum()
+        }
+    }
+}
+
+/// The coefficients and gains of a filter bank's peaks, see `FilterBank::snapshot()`. This
+/// has room for every filter so it can be copied without allocating.
+#[derive(Clone, Copy)]
+pub struct BankSnapshot {
+    sample_rate: f32,
+    peaks: [(PeakFilter, f32); MAX_FILTERS],
+    len: usize,
+    frozen: bool,
+    inverted: bool,
+}
+
+impl Default for BankSnapshot {
+    fn default() -> Self {
+        Self {
+            sample_rate: 44100.0,
+            peaks: [(PeakFilter::default(), 0.0); MAX_FILTERS],
+            len: 0,
+            frozen: false,
+            inverted: false,
         }
-        sum - gain_sum * input
+    }
+}
+
+impl BankSnapshot {
+    /// The same as `FilterBank::response()` for the bank the snapshot was taken of.
+    pub fn response(&self, freq: f32) -> Complex32 {
+        let peaks = self.peaks[..self.len].iter().copied();
+        bank_response(freq, self.sample_rate, peaks, self.frozen, self.inverted)
+    }
+}
+
+/// The response of peaks with the given filters and gains at `freq` Hz.
+fn bank_response(
+    freq: f32,
+    sample_rate: f32,
+    peaks: impl Iterator<Item = (PeakFilter, f32)>,
+    frozen: bool,
+    inverted: bool,
+) -> Complex32 {
+    let w = 2.0 * std::f64::consts::PI * freq as f64 / sample_rate as f64;
+    let z1 = Complex64::from_polar(1.0, -w);
+    let z2 = z1 * z1;
+    // The input is muted while frozen
+    let band: Complex64 = if frozen {
+        Complex64::new(0.0, 0.0)
+    } else {
+        peaks
+            .map(|(filter, gain)| (filter.response(z1, z2) - 1.0) * gain as f64)
+            .sum()
+    };
+    let response = if inverted {
+        1.0 - band / PEAK_BAND_GAIN as f64
+    } else {
+        band
+    };
+    Complex32::new(response.re as f32, response.im as f32)
+}
+
+/// All-pass filters applied to the dry signal so it stays in phase with the filter bank's
+/// peaks when the two are mixed. An all-pass is 180 degrees out at its centre, so the
+/// compensated signal's polarity is flipped to line up with the peaks there. The sections are
+/// set with `FilterBank::update_phase_compensation()`.
+#[derive(Clone)]
+pub struct PhaseCompensation {
+    sections: Vec<AllPass>,
+    /// Whether the output is negated, which stays set while no note is enabled so the dry
+    /// signal's polarity doesn't depend on the gains
+    flipped: bool,
+    sample_rate: f32,
+}
+
+impl PhaseCompensation {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            // Room for every filter the bank can have so updates don't allocate
+            sections: Vec::with_capacity(MAX_FILTERS),
+            flipped: false,
+            sample_rate,
+        }
+    }
+
+    /// Change the sample rate the dry signal runs at. This clears the sections.
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.clear();
+    }
+
+    /// Remove all sections so the dry signal passes unchanged.
+    pub fn clear(&mut self) {
+        self.sections.clear();
+        self.flipped = false;
+    }
+
+    fn polarity(&self) -> f32 {
+        if self.flipped {
+            -1.0
+        } else {
+            1.0
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for section in &mut self.sections {
+            section.z1 = 0.0;
+            section.z2 = 0.0;
+        }
+    }
+
+    /// The response at `freq` Hz. Its magnitude is always 1.
+    pub fn response(&self, freq: f32) -> Complex32 {
+        let w = 2.0 * std::f64::consts::PI * freq as f64 / self.sample_rate as f64;
+        let z1 = Complex64::from_polar(1.0, -w);
+        let z2 = z1 * z1;
+        let response = self
+            .sections
+            .iter()
+            .fold(Complex64::new(1.0, 0.0), |acc, s| acc * s.response(z1, z2));
+        Complex32::new(response.re as f32, response.im as f32) * self.polarity()
+    }
+
+    pub fn process_sample(&mut self, input: f32) -> f32 {
+        self.sections
+            .iter_mut()
+            .fold(input, |x, section| section.process(x))
+            * self.polarity()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::oversampling::Oversampler;
 
     #[test]
     fn test_note_index() {
@@ -200,6 +901,30 @@ mod tests {
         out_sum / samples as f32
     }
 
+    #[test]
+    fn test_hot_input_stays_linear() {
+        // A +20 dBFS sine drives the A4 peak to about 90, well past `FILTER_LIMIT`
+        let sr = 44100.0;
+        let mut gains = [0.0; 12];
+        gains[9] = 1.0;
+        let mut hot = FilterBank::new(sr);
+        let mut unit = FilterBank::new(sr);
+        hot.set_gains(gains);
+        unit.set_gains(gains);
+        let mut peak = 0.0_f32;
+        let mut error = 0.0_f32;
+        for n in 0..44100 {
+            let x = (2.0 * std::f32::consts::PI * 440.0 * n as f32 / sr).sin();
+            let out = hot.process_sample(10.0 * x);
+            let expected = 10.0 * unit.process_sample(x);
+            peak = peak.max(out.abs());
+            error = error.max((out - expected).abs());
+        }
+        assert!(peak > 2.0 * FILTER_LIMIT, "{peak}");
+        // Only rounding is left, the limiter would cut the peak by more than half
+        assert!(error < 1e-3 * peak, "{error} {peak}");
+    }
+
     #[test]
     fn test_sine_enabled_passes() {
         // A4 ~ 440Hz corresponds to index 9
@@ -231,6 +956,394 @@ mod tests {
         assert!(avg < 1.0);
     }
 
+    /// Excite A4 with a short sine burst and return the average output level `after` seconds
+    /// after the burst ended.
+    fn ring_level(fb: &mut FilterBank, after: f32) -> f32 {
+        let sr = 44100.0;
+        let mut gains = [0.0_f32; 12];
+        gains[9] = 1.0;
+        fb.set_gains(gains);
+        for n in 0..4410 {
+            let t = n as f32 / sr;
+            fb.process_sample((2.0 * std::f32::consts::PI * 440.0 * t).sin());
+        }
+        for _ in 0..(after * sr) as usize {
+            fb.process_sample(0.0);
+        }
+        let mut sum = 0.0;
+        for _ in 0..441 {
+            sum += fb.process_sample(0.0).abs();
+        }
+        sum / 441.0
+    }
+
+    #[test]
+    fn test_longer_decay_rings_longer() {
+        let mut natural = FilterBank::new(44100.0);
+        let mut long = FilterBank::new(44100.0);
+        long.set_decay(Some(5.0));
+        assert!(ring_level(&mut long, 2.0) > 10.0 * ring_level(&mut natural, 2.0));
+    }
+
+    #[test]
+    fn test_decay_none_restores_default() {
+        let mut fb = FilterBank::new(44100.0);
+        fb.set_decay(Some(2.0));
+        fb.set_decay(None);
+        let reference = PeakFilter::new(fb.filters[0].freq, DEFAULT_Q, PEAK_GAIN_DB, 44100.0);
+        assert_eq!(fb.filters[0].filter.a1, reference.a1);
+        assert_eq!(fb.filters[0].filter.a2, reference.a2);
+    }
+
+    #[test]
+    fn test_freeze_keeps_ringing() {
+        let mut fb = FilterBank::new(44100.0);
+        fb.set_decay(Some(1.0));
+        let before = ring_level(&mut fb, 0.0);
+        fb.set_freeze(true);
+        // Input is ignored while frozen
+        for _ in 0..44100 {
+            let out = fb.process_sample(1.0);
+            assert!(out.is_finite() && out.abs() <= FILTER_LIMIT * 108.0);
+        }
+        let mut sum = 0.0;
+        for _ in 0..441 {
+            sum += fb.process_sample(0.0).abs();
+        }
+        let after = sum / 441.0;
+        assert!(after > 0.5 * before);
+
+        // Unfreezing lets the resonance decay again
+        fb.set_freeze(false);
+        assert!(ring_level(&mut fb, 3.0) < 0.1 * after);
+    }
+
+    fn average_output(fb: &mut FilterBank, freq: f32) -> f32 {
+        let sr = 44100.0;
+        let samples = 44_100;
+        let mut out_sum = 0.0;
+        for n in 0..samples {
+            let t = n as f32 / sr;
+            let input = (2.0 * std::f32::consts::PI * freq * t).sin();
+            out_sum += fb.process_sample(input).abs();
+        }
+        out_sum / samples as f32
+    }
+
+    #[test]
+    fn test_harmonics_add_filters() {
+        let mut fb = FilterBank::new(44100.0);
+        fb.set_harmonics(Harmonics {
+            count: 3,
+            ..Harmonics::default()
+        });
+        assert!(fb.filters.len() > 108);
+        assert!(fb
+            .filters
+            .iter()
+            .all(|f| f.freq < MAX_PARTIAL_RATIO * 44100.0));
+
+        fb.set_harmonics(Harmonics::default());
+        assert_eq!(fb.filters.len(), 108);
+    }
+
+    #[test]
+    fn test_harmonics_pass_partials() {
+        let mut gains = [0.0_f32; 12];
+        gains[9] = 1.0;
+        // The 3rd harmonic of A4 at 1320 Hz doesn't line up with any note
+        let mut plain = FilterBank::new(44100.0);
+        plain.set_gains(gains);
+        let mut harmonic = FilterBank::new(44100.0);
+        harmonic.set_gains(gains);
+        harmonic.set_harmonics(Harmonics {
+            count: 1,
+            rolloff: 1.0,
+            inharmonicity: 0.0,
+        });
+        assert!(average_output(&mut harmonic, 1320.0) > 10.0 * average_output(&mut plain, 1320.0));
+    }
+
+    #[test]
+    fn test_harmonics_inharmonicity_stretches_partials() {
+        let mut fb = FilterBank::new(44100.0);
+        fb.set_harmonics(Harmonics {
+            count: 1,
+            rolloff: 0.5,
+            inharmonicity: 0.001,
+        });
+        let fundamental = fb.filters[0].freq;
+        let partial = fb.filters[NUM_NOTES].freq;
+        assert!(partial > 3.0 * fundamental);
+        assert!((fb.filters[NUM_NOTES].weight - 0.5).abs() < 1e-6);
+
+        // Changing only the rolloff keeps the filters and updates their weights
+        fb.set_harmonics(Harmonics {
+            count: 1,
+            rolloff: 0.25,
+            inharmonicity: 0.001,
+        });
+        assert_eq!(fb.filters[NUM_NOTES].freq, partial);
+        assert!((fb.filters[NUM_NOTES].weight - 0.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_inharmonicity_keeps_filter_state() {
+        let mut gains = [0.0_f32; 12];
+        gains[9] = 1.0;
+        let harmonics = Harmonics {
+            count: 1,
+            rolloff: 1.0,
+            inharmonicity: 0.0001,
+        };
+        let mut fb = FilterBank::new(44100.0);
+        fb.set_gains(gains);
+        fb.set_harmonics(harmonics);
+        // Ring the 3rd harmonic of A4
+        for n in 0..4410 {
+            fb.process_sample((2.0 * std::f32::consts::PI * 1320.0 * n as f32 / 44100.0).sin());
+        }
+        let len = fb.filters.len();
+        let partial = fb
+            .filters
+            .iter()
+            .position(|f| f.midi == 69 && f.order == 1)
+            .unwrap();
+        let state = fb.filters[partial].filter.z1;
+        assert!(state != 0.0);
+
+        fb.set_harmonics(Harmonics {
+            inharmonicity: 0.0002,
+            ..harmonics
+        });
+        assert_eq!(fb.filters.len(), len);
+        assert_eq!(fb.filters[partial].filter.z1, state);
+        assert!(fb.filters[partial].freq > 3.0 * 440.0 * (1.0_f32 + 0.0001 * 9.0).sqrt());
+    }
+
+    #[test]
+    fn test_tuning_moves_centre_frequencies() {
+        let mut fb = FilterBank::new(44100.0);
+        let a4 = fb.filters[69 - 12].freq;
+        let c4 = fb.filters[60 - 12].freq;
+        let mut detune = [0.0; 12];
+        detune[9] = 10.0;
+        fb.set_tuning(Tuning {
+            detune,
+            ..Tuning::default()
+        });
+        let ratio = fb.filters[69 - 12].freq / a4;
+        assert!((1200.0 * ratio.log2() - 10.0).abs() < 0.01);
+        // Other pitch classes are untouched
+        assert_eq!(fb.filters[60 - 12].freq, c4);
+    }
+
+    #[test]
+    fn test_stretch_widens_octaves() {
+        let mut fb = FilterBank::new(44100.0);
+        fb.set_tuning(Tuning {
+            stretch: 1.0,
+            ..Tuning::default()
+        });
+        let low = fb.filters[0].freq;
+        let high = fb.filters[NUM_NOTES - 1].freq;
+        // C0 is tuned flat and B8 sharp
+        assert!(low < 440.0 * 2.0_f32.powf((12.0 - 69.0) / 12.0));
+        assert!(high > 440.0 * 2.0_f32.powf((119.0 - 69.0) / 12.0));
+        // A4 stays at the reference
+        assert!((fb.filters[69 - 12].freq - 440.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_detuned_sine_passes() {
+        // A source tuned 30 cents sharp is only picked up when the bank is detuned to match
+        let freq = 440.0 * 2.0_f32.powf(30.0 / 1200.0);
+        let mut gains = [0.0_f32; 12];
+        gains[9] = 1.0;
+        let mut plain = FilterBank::new(44100.0);
+        plain.set_gains(gains);
+        let mut detuned = FilterBank::new(44100.0);
+        detuned.set_gains(gains);
+        let mut detune = [0.0; 12];
+        detune[9] = 30.0;
+        detuned.set_tuning(Tuning {
+            detune,
+            ..Tuning::default()
+        });
+        assert!(average_output(&mut detuned, freq) > 5.0 * average_output(&mut plain, freq));
+    }
+
+    #[test]
+    fn test_reference_moves_all_notes() {
+        let mut fb = FilterBank::new(44100.0);
+        fb.set_tuning(Tuning {
+            reference: 432.0,
+            ..Tuning::default()
+        });
+        assert!((fb.filters[69 - 12].freq - 432.0).abs() < 1e-3);
+        assert!((fb.filters[81 - 12].freq - 864.0).abs() < 1e-2);
+    }
+
+    fn process_sine_inverted(freq: f32, enabled_note: usize) -> f32 {
+        let sr = 44100.0;
+        let mut fb = FilterBank::new(sr);
+        let mut gains = [0.0_f32; 12];
+        gains[enabled_note] = 1.0;
+        fb.set_gains(gains);
+        fb.set_invert(true);
+
+        let mut out_sum = 0.0;
+        for n in 0..44_100 {
+            let input = (2.0 * std::f32::consts::PI * freq * n as f32 / sr).sin();
+            let out = fb.process_sample(input);
+            // Skip the filters' settling time
+            if n >= 22_050 {
+                out_sum += out.abs();
+            }
+        }
+        out_sum / 22_050.0
+    }
+
+    #[test]
+    fn test_invert_notches_enabled_notes() {
+        // The average of a rectified unit sine is 2/pi
+        let sine_level = 2.0 / std::f32::consts::PI;
+        assert!(process_sine_inverted(440.0, 9) < 0.05 * sine_level);
+        assert!((process_sine_inverted(440.0, 0) - sine_level).abs() < 0.05 * sine_level);
+        assert!((process_sine_inverted(466.16, 9) - sine_level).abs() < 0.1 * sine_level);
+    }
+
+    #[test]
+    fn test_invert_without_notes_passes_input() {
+        let mut fb = FilterBank::new(44100.0);
+        fb.set_gains([0.0; 12]);
+        fb.set_invert(true);
+        assert_eq!(fb.process_sample(0.5), 0.5);
+    }
+
+    #[test]
+    fn test_stems_add_up_to_output() {
+        let sr = 44100.0;
+        let mut fb = FilterBank::new(sr);
+        let mut split = FilterBank::new(sr);
+        let gains = [1.0, 0.0, 0.5, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0];
+        fb.set_gains(gains);
+        split.set_gains(gains);
+        let mut stems = [0.0; 12];
+        for n in 0..4410 {
+            let t = n as f32 / sr;
+            let input = (2.0 * std::f32::consts::PI * 261.63 * t).sin()
+                + 0.5 * (2.0 * std::f32::consts::PI * 392.0 * t).sin();
+            let out = split.process_sample_split(input, &mut stems);
+            assert!((fb.process_sample(input) - out).abs() < 1e-4);
+            assert!((stems.iter().sum::<f32>() - out).abs() < 1e-4);
+            // Disabled pitch classes stay silent
+            assert_eq!(stems[1], 0.0);
+        }
+        // Most of the energy ends up in the stems of the input's notes
+        assert!(stems[0].abs() + stems[7].abs() > stems[2].abs() + stems[4].abs());
+    }
+
+    #[test]
+    fn test_hum_removes_series() {
+        let sr = 44100.0;
+        let mut fb = FilterBank::new(sr);
+        // The note gains don't apply to the hum series
+        fb.set_gains([0.0; 12]);
+        fb.set_invert(true);
+        fb.set_target(Target::Hum(Hum {
+            fundamental: 50.0,
+            harmonics: 8,
+        }));
+        let hum = |t: f32| {
+            (1..=4)
+                .map(|n| (2.0 * std::f32::consts::PI * 50.0 * n as f32 * t).sin())
+                .sum::<f32>()
+        };
+        let voice = |t: f32| 0.5 * (2.0 * std::f32::consts::PI * 1000.0 * t).sin();
+        let mut error = 0.0;
+        let mut hum_energy = 0.0;
+        for n in 0..3 * 44100 {
+            let t = n as f32 / sr;
+            let out = fb.process_sample(hum(t) + voice(t));
+            // The 50 Hz notch takes a while to settle
+            if n >= 2 * 44100 {
+                error += (out - voice(t)).powi(2);
+                hum_energy += hum(t).powi(2);
+            }
+        }
+        // The notches' skirts overlap, so the hum is reduced by more than 23 dB rather than
+        // removed completely
+        assert!(error < 0.005 * hum_energy, "{error} {hum_energy}");
+    }
+
+    #[test]
+    fn test_hum_filters_limited_by_nyquist() {
+        let mut fb = FilterBank::new(44100.0);
+        fb.set_target(Target::Hum(Hum {
+            fundamental: 5000.0,
+            harmonics: 100,
+        }));
+        // 5, 10 and 15 kHz, 20 kHz is too close to Nyquist
+        assert_eq!(fb.custom_filters.len(), 3);
+        assert_eq!(fb.custom_filters[0].idx, note_index("d#").unwrap());
+        fb.set_target(Target::Notes);
+        assert!(fb.custom_filters.is_empty());
+    }
+
+    #[test]
+    fn test_frequency_list_passes_listed_frequencies() {
+        let sr = 44100.0;
+        let run = |freq: f32| {
+            let mut fb = FilterBank::new(sr);
+            fb.set_frequency_list(&[
+                Resonance {
+                    freq: 1171.0,
+                    gain: 1.0,
+                    q: 100.0,
+                },
+                Resonance {
+                    freq: 30000.0,
+                    gain: 1.0,
+                    q: 100.0,
+                },
+            ]);
+            fb.set_target(Target::List);
+            // The entry above Nyquist is left out
+            assert_eq!(fb.custom_filters.len(), 1);
+            let mut sum = 0.0;
+            for n in 0..44100 {
+                let out =
+                    fb.process_sample((2.0 * std::f32::consts::PI * freq * n as f32 / sr).sin());
+                if n >= 22050 {
+                    sum += out.abs();
+                }
+            }
+            sum / 22050.0
+        };
+        // 1171 Hz lies between D6 and D#6, and A4 isn't in the list
+        assert!(run(1171.0) > 10.0 * run(440.0));
+    }
+
+    #[test]
+    fn test_frequency_list_skips_invalid_entries() {
+        let mut fb = FilterBank::new(44100.0);
+        let resonance = |freq: f32, gain: f32, q: f32| Resonance { freq, gain, q };
+        fb.set_frequency_list(&[
+            resonance(f32::NAN, 1.0, 100.0),
+            resonance(-100.0, 1.0, 100.0),
+            resonance(200.0, 1.0, 0.0),
+            resonance(300.0, -1.0, 100.0),
+            resonance(400.0, 1.0, f32::INFINITY),
+            resonance(500.0, 0.5, 100.0),
+        ]);
+        fb.set_target(Target::List);
+        assert_eq!(fb.custom_filters.len(), 1);
+        assert_eq!(fb.custom_filters[0].freq, 500.0);
+        let out: f32 = (0..4410).map(|n| fb.process_sample((n as f32).sin())).sum();
+        assert!(out.is_finite());
+    }
+
     #[test]
     fn test_nearby_frequency_attenuated() {
         // 450Hz should be much quieter than 440Hz when A4 is enabled
@@ -238,4 +1351,199 @@ mod tests {
         let off = process_sine(450.0, 9);
         assert!(pass > 10.0 * off);
     }
+
+    #[test]
+    fn test_set_sample_rate_keeps_layout() {
+        let mut fb = FilterBank::new(44100.0);
+        fb.set_harmonics(Harmonics {
+            count: 3,
+            ..Harmonics::default()
+        });
+        let partials = fb.filters.len();
+        fb.set_sample_rate(4.0 * 44100.0);
+        // Partials that were above the old Nyquist limit are now included
+        assert!(fb.filters.len() > partials);
+        fb.set_sample_rate(44100.0);
+        assert_eq!(fb.filters.len(), partials);
+    }
+
+    /// Energy of the bank's settled output for a sine `cents` away from a note, with the bank
+    /// running at `factor` times 44.1 kHz.
+    fn oversampled_energy(midi: u8, cents: f32, factor: usize) -> f32 {
+        let sr = 44100.0;
+        let mut fb = FilterBank::new(sr * factor as f32);
+        let mut gains = [0.0; 12];
+        gains[midi as usize % 12] = 1.0;
+        fb.set_gains(gains);
+        let mut oversampler = Oversampler::new(factor);
+        let freq = 440.0 * 2.0_f32.powf((midi as f32 - 69.0 + cents / 100.0) / 12.0);
+        let mut energy = 0.0;
+        for n in 0..8192 {
+            let x = (2.0 * std::f32::consts::PI * freq * n as f32 / sr).sin();
+            let y = oversampler.process(x, |x| fb.process_sample(x));
+            if n >= 4096 {
+                energy += y * y;
+            }
+        }
+        energy
+    }
+
+    /// The offset in cents of the loudest response around a note, found by scanning sines in
+    /// one cent steps.
+    fn peak_offset_cents(midi: u8, factor: usize) -> i32 {
+        (-10..=10)
+            .map(|cents| (cents, oversampled_energy(midi, cents as f32, factor)))
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap()
+            .0
+    }
+
+    #[test]
+    fn test_oversampled_peaks_match_targets() {
+        // B7 and B8, the top octaves where the peaks are closest to Nyquist
+        for midi in [107, 119] {
+            for factor in [2, 4, 8] {
+                let offset = peak_offset_cents(midi, factor);
+                assert!(
+                    offset.abs() <= 3,
+                    "midi {midi} at {factor}x: {offset} cents"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_oversampling_undoes_cramping() {
+        // The bilinear transform squeezes the peaks close to Nyquist, so 10 cents off B8 the
+        // bank's band-pass output is quieter relative to the centre than the analog prototype's
+        let a = 10.0_f32.powf(PEAK_GAIN_DB / 40.0);
+        let s = Complex32::new(0.0, 2.0_f32.powf(10.0 / 1200.0));
+        let peak = (s * s + s * a / DEFAULT_Q + 1.0) / (s * s + s / (a * DEFAULT_Q) + 1.0);
+        let analog = (peak - 1.0).norm() / PEAK_BAND_GAIN;
+        let error = |factor: usize| {
+            let ratio = (oversampled_energy(119, 10.0, factor)
+                / oversampled_energy(119, 0.0, factor))
+            .sqrt();
+            (ratio / analog - 1.0).abs()
+        };
+        let errors = [1, 2, 4, 8].map(error);
+        assert!(errors[0] > 0.1, "{errors:?}");
+        assert!(errors[1] < 0.05, "{errors:?}");
+        // At 8x the single precision filter state limits the accuracy
+        assert!(errors[2] < 0.02 && errors[3] < 0.02, "{errors:?}");
+    }
+
+    /// Gain and phase shift of the bank for a sine, measured by correlating the settled output
+    /// with a sine and a cosine.
+    fn measure_response(fb: &mut FilterBank, freq: f32) -> Complex32 {
+        let sr = fb.sample_rate;
+        let w = 2.0 * std::f32::consts::PI * freq / sr;
+        let settle = sr as usize;
+        let len = sr as usize / 2;
+        let mut sum = Complex32::new(0.0, 0.0);
+        for n in 0..settle + len {
+            // Keep the phase accurate over long runs
+            let phase = (w as f64 * n as f64) % std::f64::consts::TAU;
+            let out = fb.process_sample((phase as f32).sin());
+            if n >= settle {
+                sum += Complex32::new(phase.sin() as f32, phase.cos() as f32) * out;
+            }
+        }
+        sum * 2.0 / len as f32
+    }
+
+    #[test]
+    fn test_response_matches_measurement() {
+        let mut gains = [0.0; 12];
+        gains[9] = 1.0;
+        gains[0] = 0.5;
+        for inverted in [false, true] {
+            // The filters' single precision state drifts from the ideal response in the low
+            // octaves, so the notes are checked from A4 up
+            for freq in [440.0, 442.0, 523.25, 600.0, 1760.0] {
+                let mut fb = FilterBank::new(44100.0);
+                fb.set_gains(gains);
+                fb.set_invert(inverted);
+                let expected = fb.response(freq);
+                let measured = measure_response(&mut fb, freq);
+                assert!(
+                    (measured - expected).norm() < 0.02 * expected.norm().max(1.0),
+                    "{freq} Hz, inverted {inverted}: {measured} {expected}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_response_of_peaks() {
+        let mut fb = FilterBank::new(44100.0);
+        let mut gains = [0.0; 12];
+        gains[9] = 1.0;
+        fb.set_gains(gains);
+        // The peaks leave a gain of 9 without a phase shift at their centres
+        let peak = fb.response(440.0);
+        assert!((peak.re - PEAK_BAND_GAIN).abs() < 0.05, "{peak}");
+        assert!(peak.im.abs() < 0.05, "{peak}");
+        // A single peak filter is selective enough to reject a sine 10 Hz away
+        let filter = PeakFilter::new(440.0, DEFAULT_Q, PEAK_GAIN_DB, 44100.0);
+        let band = |freq: f64| {
+            let z1 = Complex64::from_polar(1.0, -2.0 * std::f64::consts::PI * freq / 44100.0);
+            (filter.response(z1, z1 * z1) - 1.0).norm()
+        };
+        assert!(band(440.0) > 10.0 * band(450.0));
+
+        fb.set_invert(true);
+        assert!(fb.response(440.0).norm() < 0.01);
+        assert!((fb.response(300.0).norm() - 1.0).abs() < 0.01);
+        fb.set_invert(false);
+        fb.set_freeze(true);
+        assert_eq!(fb.response(440.0), Complex32::new(0.0, 0.0));
+    }
+
+    #[test]
+    fn test_phase_compensation_follows_peaks() {
+        let sr = 44100.0;
+        let mut fb = FilterBank::new(sr);
+        let mut gains = [0.0; 12];
+        gains[9] = 1.0;
+        fb.set_gains(gains);
+        let mut compensation = PhaseCompensation::new(sr);
+        fb.update_phase_compensation(&mut compensation);
+        // Only the A filters are compensated, one per octave
+        assert_eq!(compensation.sections.len(), NUM_NOTES / 12);
+
+        // Within the band-pass component's bandwidth, about 1.4 Hz at A4, the compensated dry
+        // signal follows the peak while the plain one drifts up to 45 degrees away
+        let degrees = |z: Complex32| z.arg().abs().to_degrees();
+        for offset in [-0.7, -0.35, 0.0, 0.35, 0.7] {
+            let freq = 440.0 + offset;
+            let peak = fb.response(freq);
+            let dry = compensation.response(freq);
+            assert!((dry.norm() - 1.0).abs() < 1e-3);
+            assert!(degrees(peak * dry.conj()) < 10.0, "{freq} Hz");
+        }
+        assert!(degrees(fb.response(440.7)) > 40.0);
+        // Far from the peaks only the polarity is flipped
+        let far = compensation.response(311.13);
+        assert!((far + 1.0).norm() < 0.05, "{far}");
+
+        // Processing matches the response
+        let freq = 441.0;
+        let mut phase = PhaseCompensation::new(sr);
+        fb.update_phase_compensation(&mut phase);
+        let sine = |n: usize| (2.0 * std::f32::consts::PI * freq * n as f32 / sr).sin();
+        let mut out = 0.0;
+        for n in 0..2 * sr as usize {
+            out = phase.process_sample(sine(n));
+        }
+        let expected = compensation.response(freq);
+        let n = 2.0 * sr - 1.0;
+        let w = 2.0 * std::f32::consts::PI * freq * n / sr;
+        assert!((out - expected.norm() * (w + expected.arg()).sin()).abs() < 0.01);
+
+        // The notches are already in phase with the dry signal
+        fb.set_invert(true);
+        fb.update_phase_compensation(&mut compensation);
+        assert_eq!(compensation.response(440.7), Complex32::new(1.0, 0.0));
+    }
 }
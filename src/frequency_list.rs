@@ -0,0 +1,290 @@
+// User defined lists of resonance frequencies for the filter bank
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+
+use crate::filterbank::{max_frequency, DEFAULT_Q};
+
+/// The most entries a list can have. The filter bank reserves room for this many filters.
+pub const MAX_RESONANCES: usize = 128;
+
+fn default_gain() -> f32 {
+    1.0
+}
+
+/// Entries without a `Q` use the same one as the notes.
+fn default_q() -> f32 {
+    DEFAULT_Q
+}
+
+/// A single peak of the filter bank.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Resonance {
+    /// Centre frequency in Hz.
+    pub freq: f32,
+    /// Linear gain, like the note gains.
+    #[serde(default = "default_gain")]
+    pub gain: f32,
+    #[serde(default = "default_q")]
+    pub q: f32,
+}
+
+impl Resonance {
+    /// Whether the frequency and `Q` are positive and the gain isn't negative.
+    pub fn is_valid(&self) -> bool {
+        [self.freq, self.q]
+            .iter()
+            .all(|value| value.is_finite() && *value > 0.0)
+            && self.gain.is_finite()
+            && self.gain >= 0.0
+    }
+}
+
+/// The list of resonances used instead of the notes. This is stored in the plugin state.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FrequencyList {
+    pub resonances: Vec<Resonance>,
+}
+
+/// Error returned when a frequency list can't be loaded or doesn't fit the sample rate.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FrequencyListError {
+    /// The file couldn't be read
+    Io(String),
+    /// The text looked like JSON but couldn't be parsed
+    Json(String),
+    /// A line of the text format couldn't be parsed. Lines are counted from 1.
+    Syntax {
+        line: usize,
+    },
+    TooManyResonances,
+    /// An entry has a frequency, gain or `Q` that isn't a positive number. Entries are counted
+    /// from 1.
+    InvalidValue {
+        entry: usize,
+    },
+    /// An entry is too close to or above the Nyquist frequency
+    AboveNyquist {
+        entry: usize,
+        freq: f32,
+        max: f32,
+    },
+}
+
+impl fmt::Display for FrequencyListError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrequencyListError::Io(error) => write!(f, "could not read the file: {error}"),
+            FrequencyListError::Json(error) => write!(f, "invalid JSON: {error}"),
+            FrequencyListError::Syntax { line } => {
+                write!(f, "line {line}: expected a frequency, gain and Q")
+            }
+            FrequencyListError::TooManyResonances => {
+                write!(f, "more than {MAX_RESONANCES} resonances")
+            }
+            FrequencyListError::InvalidValue { entry } => {
+                write!(f, "entry {entry}: frequency and Q must be positive, gain can't be negative")
+            }
+            FrequencyListError::AboveNyquist { entry, freq, max } => write!(
+                f,
+                "entry {entry}: {freq} Hz is too close to the Nyquist frequency, the limit is {max:.0} Hz"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FrequencyListError {}
+
+impl FrequencyList {
+    /// Parse a list from JSON or from plain text. JSON is either the stored form,
+    /// `{"resonances": [...]}`, or a bare array of `{"freq", "gain", "q"}` objects where gain and
+    /// `Q` are optional. The text form has one resonance per line as a frequency optionally
+    /// followed by a gain and a `Q`, separated by spaces or commas. Everything after a `#` is
+    /// ignored.
+    pub fn parse(text: &str) -> Result<Self, FrequencyListError> {
+        let trimmed = text.trim_start();
+        let list = if trimmed.starts_with('{') {
+            serde_json::from_str(trimmed).map_err(|e| FrequencyListError::Json(e.to_string()))?
+        } else if trimmed.starts_with('[') {
+            FrequencyList {
+                resonances: serde_json::from_str(trimmed)
+                    .map_err(|e| FrequencyListError::Json(e.to_string()))?,
+            }
+        } else {
+            let mut resonances = Vec::new();
+            for (i, line) in text.lines().enumerate() {
+                let line = line.split('#').next().unwrap_or("");
+                let values = line
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|value| !value.is_empty())
+                    .map(str::parse::<f32>)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| FrequencyListError::Syntax { line: i + 1 })?;
+                let resonance = match values[..] {
+                    [] => continue,
+                    [freq] => Resonance {
+                        freq,
+                        gain: default_gain(),
+                        q: DEFAULT_Q,
+                    },
+                    [freq, gain] => Resonance {
+                        freq,
+                        gain,
+                        q: DEFAULT_Q,
+                    },
+                    [freq, gain, q] => Resonance { freq, gain, q },
+                    _ => return Err(FrequencyListError::Syntax { line: i + 1 }),
+                };
+                resonances.push(resonance);
+            }
+            FrequencyList { resonances }
+        };
+
+        if list.resonances.len() > MAX_RESONANCES {
+            return Err(FrequencyListError::TooManyResonances);
+        }
+        if let Some(i) = list.resonances.iter().position(|r| !r.is_valid()) {
+            return Err(FrequencyListError::InvalidValue { entry: i + 1 });
+        }
+        Ok(list)
+    }
+
+    /// Read and parse a list from a file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, FrequencyListError> {
+        let text =
+            std::fs::read_to_string(path).map_err(|e| FrequencyListError::Io(e.to_string()))?;
+        Self::parse(&text)
+    }
+
+    /// Check every entry's values and compare it against the highest frequency the filter bank
+    /// can run at `sample_rate`. Lists restored from the plugin state skip `parse()`, so this
+    /// repeats its checks. The filter bank leaves out entries that fail them.
+    pub fn validate(&self, sample_rate: f32) -> Result<(), FrequencyListError> {
+        let max = max_frequency(sample_rate);
+        for (i, resonance) in self.resonances.iter().enumerate() {
+            if !resonance.is_valid() {
+                return Err(FrequencyListError::InvalidValue { entry: i + 1 });
+            }
+            if resonance.freq >= max {
+                return Err(FrequencyListError::AboveNyquist {
+                    entry: i + 1,
+                    freq: resonance.freq,
+                    max,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// The list in the text form `parse()` reads, one `frequency gain Q` line per resonance.
+    pub fn to_text(&self) -> String {
+        self.resonances
+            .iter()
+            .map(|r| format!("{} {} {}\n", r.freq, r.gain, r.q))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_text() {
+        let list = FrequencyList::parse(
+            "# Room modes\n\
+             43.5\n\
+             87, 0.5\n\
+             \n\
+             130.5 0.25 40 # third mode\n",
+        )
+        .unwrap();
+        assert_eq!(
+            list.resonances,
+            vec![
+                Resonance {
+                    freq: 43.5,
+                    gain: 1.0,
+                    q: DEFAULT_Q
+                },
+                Resonance {
+                    freq: 87.0,
+                    gain: 0.5,
+                    q: DEFAULT_Q
+                },
+                Resonance {
+                    freq: 130.5,
+                    gain: 0.25,
+                    q: 40.0
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_json() {
+        let list =
+            FrequencyList::parse(r#"[{"freq": 440.0}, {"freq": 1171.0, "gain": 0.3, "q": 200}]"#)
+                .unwrap();
+        assert_eq!(list.resonances.len(), 2);
+        assert_eq!(list.resonances[0].gain, 1.0);
+        assert_eq!(list.resonances[1].q, 200.0);
+
+        // The stored form round trips
+        let stored = serde_json::to_string(&list).unwrap();
+        assert_eq!(FrequencyList::parse(&stored), Ok(list));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            FrequencyList::parse("100\n200 abc"),
+            Err(FrequencyListError::Syntax { line: 2 })
+        );
+        assert_eq!(
+            FrequencyList::parse("100 1 10 5"),
+            Err(FrequencyListError::Syntax { line: 1 })
+        );
+        assert_eq!(
+            FrequencyList::parse("100\n-5"),
+            Err(FrequencyListError::InvalidValue { entry: 2 })
+        );
+        assert!(matches!(
+            FrequencyList::parse("[{\"gain\": 1}]"),
+            Err(FrequencyListError::Json(_))
+        ));
+        let too_many = "100\n".repeat(MAX_RESONANCES + 1);
+        assert_eq!(
+            FrequencyList::parse(&too_many),
+            Err(FrequencyListError::TooManyResonances)
+        );
+    }
+
+    #[test]
+    fn test_validate_against_nyquist() {
+        let list = FrequencyList::parse("1000\n21000").unwrap();
+        assert_eq!(list.validate(48000.0), Ok(()));
+        let error = list.validate(44100.0).unwrap_err();
+        assert!(matches!(
+            error,
+            FrequencyListError::AboveNyquist { entry: 2, .. }
+        ));
+        assert!(error.to_string().starts_with("entry 2: 21000 Hz"));
+
+        // Stored lists are checked like parsed ones
+        let stored: FrequencyList =
+            serde_json::from_str(r#"{"resonances": [{"freq": 100.0, "q": -1.0}]}"#).unwrap();
+        assert_eq!(
+            stored.validate(44100.0),
+            Err(FrequencyListError::InvalidValue { entry: 1 })
+        );
+    }
+
+    #[test]
+    fn test_text_round_trips() {
+        let list = FrequencyList::parse("43.5\n87 0.5\n130.25 0.25 40").unwrap();
+        assert_eq!(FrequencyList::parse(&list.to_text()), Ok(list));
+        assert_eq!(FrequencyList::default().to_text(), "");
+    }
+}
@@ -0,0 +1,171 @@
+// Mains hum detection for the filter bank's hum mode
+
+use nih_plug::prelude::Enum;
+use std::f32::consts::PI;
+
+/// Length of a detection window in seconds.
+const WINDOW_SECONDS: f32 = 0.5;
+/// How many harmonics of each mains frequency are compared.
+const DETECTION_HARMONICS: usize = 3;
+/// One mains frequency has to carry this much more power than the other (6 dB) to be detected.
+const DETECTION_RATIO: f32 = 4.0;
+/// Hum below -80 dBFS is ignored.
+const MIN_AMPLITUDE: f32 = 1e-4;
+/// The mains frequencies that are told apart.
+const MAINS_FREQUENCIES: [f32; 2] = [50.0, 60.0];
+
+/// Where the fundamental of the hum series comes from.
+#[derive(Enum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mains {
+    /// Detect 50 or 60 Hz from the input
+    #[id = "auto"]
+    Auto,
+    #[id = "50hz"]
+    #[name = "50 Hz"]
+    Hz50,
+    #[id = "60hz"]
+    #[name = "60 Hz"]
+    Hz60,
+    /// Use the hum frequency parameter
+    #[id = "custom"]
+    Custom,
+}
+
+/// Measures the power of a single frequency over a block of samples.
+#[derive(Clone, Copy, Debug)]
+struct Goertzel {
+    coeff: f32,
+    s1: f32,
+    s2: f32,
+}
+
+impl Goertzel {
+    fn new(freq: f32, sample_rate: f32) -> Self {
+        Self {
+            coeff: 2.0 * (2.0 * PI * freq / sample_rate).cos(),
+            s1: 0.0,
+            s2: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: f32) {
+        let s = input + self.coeff * self.s1 - self.s2;
+        self.s2 = self.s1;
+        self.s1 = s;
+    }
+
+    /// The amplitude of the frequency over the last `len` samples. This starts a new block.
+    fn take_amplitude(&mut self, len: usize) -> f32 {
+        let power = self.s1 * self.s1 + self.s2 * self.s2 - self.coeff * self.s1 * self.s2;
+        self.s1 = 0.0;
+        self.s2 = 0.0;
+        2.0 * power.max(0.0).sqrt() / len as f32
+    }
+}
+
+/// Decides between 50 and 60 Hz mains by comparing the first harmonics of both in windows of
+/// half a second. The last decision is kept while the input is quiet or ambiguous.
+#[derive(Clone, Debug)]
+pub struct MainsDetector {
+    window_len: usize,
+    count: usize,
+    filters: [[Goertzel; DETECTION_HARMONICS]; 2],
+    detected: Option<f32>,
+}
+
+impl MainsDetector {
+    pub fn new(sample_rate: f32) -> Self {
+        let filters = MAINS_FREQUENCIES.map(|mains| {
+            std::array::from_fn(|k| Goertzel::new((k + 1) as f32 * mains, sample_rate))
+        });
+        Self {
+            window_len: (WINDOW_SECONDS * sample_rate) as usize,
+            count: 0,
+            filters,
+            detected: None,
+        }
+    }
+
+    /// Forget the detected frequency.
+    pub fn reset(&mut self) {
+        self.count = 0;
+        self.detected = None;
+        for filter in self.filters.iter_mut().flatten() {
+            filter.s1 = 0.0;
+            filter.s2 = 0.0;
+        }
+    }
+
+    /// The detected mains frequency in Hz, or `None` if no hum has been found yet.
+    pub fn detected(&self) -> Option<f32> {
+        self.detected
+    }
+
+    pub fn process_sample(&mut self, input: f32) {
+        for filter in self.filters.iter_mut().flatten() {
+            filter.process(input);
+        }
+        self.count += 1;
+        if self.count < self.window_len {
+            return;
+        }
+
+        let len = self.count;
+        self.count = 0;
+        let [fifty, sixty] = self.filters.each_mut().map(|filters| {
+            filters
+                .iter_mut()
+                .map(|filter| filter.take_amplitude(len).powi(2))
+                .sum::<f32>()
+        });
+        let min_power = MIN_AMPLITUDE * MIN_AMPLITUDE;
+        if fifty > min_power && fifty > DETECTION_RATIO * sixty {
+            self.detected = Some(MAINS_FREQUENCIES[0]);
+        } else if sixty > min_power && sixty > DETECTION_RATIO * fifty {
+            self.detected = Some(MAINS_FREQUENCIES[1]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One second of mains hum with a few harmonics under a louder tone.
+    fn detect(mains: f32, level: f32) -> Option<f32> {
+        let sr = 44100.0;
+        let mut detector = MainsDetector::new(sr);
+        for n in 0..44100 {
+            let t = n as f32 / sr;
+            let hum: f32 = (1..=3)
+                .map(|k| (2.0 * PI * mains * k as f32 * t).sin() / k as f32)
+                .sum();
+            detector.process_sample(level * hum + 0.3 * (2.0 * PI * 440.0 * t).sin());
+        }
+        detector.detected()
+    }
+
+    #[test]
+    fn test_detects_mains() {
+        assert_eq!(detect(50.0, 0.01), Some(50.0));
+        assert_eq!(detect(60.0, 0.01), Some(60.0));
+        // Slightly off-frequency mains are still recognized
+        assert_eq!(detect(50.3, 0.01), Some(50.0));
+    }
+
+    #[test]
+    fn test_ignores_missing_hum() {
+        assert_eq!(detect(50.0, 0.0), None);
+    }
+
+    #[test]
+    fn test_reset_forgets_detection() {
+        let mut detector = MainsDetector::new(1000.0);
+        for n in 0..1000 {
+            detector.process_sample((2.0 * PI * 60.0 * n as f32 / 1000.0).sin());
+        }
+        assert_eq!(detector.detected(), Some(60.0));
+        detector.reset();
+        assert_eq!(detector.detected(), None);
+    }
+}
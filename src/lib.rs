@@ -1,11 +1,58 @@
+use atomic_float::AtomicF32;
 use nih_plug::prelude::*;
+use nih_plug_egui::EguiState;
 use rayon::prelude::*;
 use rayon::ThreadPoolBuilder;
-use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex, RwLock};
+use triple_buffer::Output;
 
-use crate::filterbank::FilterBank;
+use crate::analyzer::{Analysis, Analyzer};
+use crate::chord::{parse_chord, ChordWeights, DEFAULT_CHORDS};
+use crate::filterbank::{Harmonics, Hum, PhaseCompensation, Target, MAX_HUM_HARMONICS};
+use crate::frequency_list::FrequencyList;
+use crate::hum::{Mains, MainsDetector};
+use crate::limiter::DelayLine;
+use crate::mask::{morph, NoteMaskParams};
+use crate::oversampling::Quality;
+use crate::response::ResponsePublisher;
+use crate::saturation::Character;
+use crate::scales::{Root, Scale};
+use crate::sequencer::{Pattern, TransportInfo};
+use crate::tuning::{DetuneParams, Tuning, TuningEstimator};
+use crate::wet::{Engine, WetPath, WetSettings, WetSnapshot};
 
+pub mod analyzer;
+pub mod autogain;
+pub mod chord;
+pub mod editor;
 pub mod filterbank;
+pub mod frequency_list;
+pub mod hum;
+pub mod limiter;
+pub mod mask;
+pub mod oversampling;
+pub mod response;
+pub mod saturation;
+pub mod scales;
+pub mod sequencer;
+pub mod spectral;
+pub mod transient;
+pub mod tuning;
+pub mod wet;
+
+/// The number of entries of the chord list that can be reached with the chord parameter.
+const MAX_CHORDS: i32 = 16;
+/// Default time constant of the auto gain in milliseconds.
+const DEFAULT_AUTO_GAIN_TIME: f32 = 300.0;
+/// Default length of the bypass crossfade in milliseconds.
+const DEFAULT_BYPASS_TIME: f32 = 20.0;
+/// Upper bound for the latency of the wet path besides the spectral engine, in seconds.
+const MAX_LATENCY_SECONDS: f32 = 0.1;
+/// Names of the auxiliary outputs in the layouts with one stem per pitch class.
+const STEM_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
 
 // This is a shortened version of the gain example with most comments removed, check out
 // https://github.com/robbert-vdh/nih-plug/blob/master/plugins/examples/gain/src/lib.rs to get
@@ -13,9 +60,86 @@ pub mod filterbank;
 
 struct ColourizerRs {
     params: Arc<ColourizerRsParams>,
-    filterbank: FilterBank,
-    filterbanks: Vec<FilterBank>,
+    /// The wet path for the mono sum, and one per channel in multi-channel mode
+    wet: WetPath,
+    wet_paths: Vec<WetPath>,
+    /// Delays the dry signal by the wet path's latency so the two stay aligned
+    dry_delays: Vec<DelayLine>,
+    /// Shifts the phase of every channel's dry signal to match the filter bank when the phase
+    /// alignment is enabled
+    dry_phase: Vec<PhaseCompensation>,
+    /// The latency last reported to the host
+    latency: u32,
+    /// The limiter's largest gain reduction in decibels since the editor last read it
+    gain_reduction: Arc<AtomicF32>,
+    /// Hands the wet path's frequency response to the editor
+    response: ResponsePublisher,
+    response_output: Arc<Mutex<Output<WetSnapshot>>>,
+    /// Analyzes the input and output for the editor's spectrum and chroma meters
+    analyzer: Analyzer,
+    analysis_output: Arc<Mutex<Output<Analysis>>>,
     sample_rate: f32,
+    /// The note gains at the end of the last block, kept when the chord list is briefly locked
+    note_gains: [f32; 12],
+    /// Per-sample note gains, which only change within a block when the sequencer is playing
+    note_gain_values: Vec<[f32; 12]>,
+    /// Per-sample morph amounts shared by all channels in multi-channel mode
+    morph_values: Vec<f32>,
+    /// Per-sample input trim and output gain shared by all channels in multi-channel mode
+    input_trim_values: Vec<f32>,
+    output_gain_values: Vec<f32>,
+    /// Position of the bypass crossfade, from 0 when processing to 1 when bypassed
+    bypass_position: f32,
+    /// The crossfade's position for every sample of the current block
+    bypass_values: Vec<f32>,
+    /// Everything the output is missing compared to the delayed input, per channel. This is sent
+    /// to the auxiliary output when the host connects it.
+    residual: Vec<Vec<f32>>,
+    /// Every pitch class' share of the wet signal per channel and sample
+    stems: Vec<Vec<[f32; 12]>>,
+    /// What the auxiliary outputs of the current layout carry
+    aux_output: AuxOutput,
+    /// Analyzes the input's tuning while the learn parameter is enabled
+    tuning_estimator: TuningEstimator,
+    /// Listens for mains hum while the hum mode detects the mains frequency
+    mains_detector: MainsDetector,
+    /// Whether the estimator ran during the last block, used to start every learn pass afresh
+    learning: bool,
+}
+
+/// The contents of the auxiliary outputs, depending on the audio IO layout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum AuxOutput {
+    None,
+    /// Everything the main output leaves out
+    Residual,
+    /// One output per pitch class
+    Stems,
+}
+
+/// How the dry and wet signals are weighted by `Dry/Wet`.
+#[derive(Enum, Clone, Copy, Debug, PartialEq, Eq)]
+enum MixLaw {
+    /// The gains add up to 1, which dips uncorrelated signals by 3 dB at the centre
+    #[id = "linear"]
+    Linear,
+    /// The squared gains add up to 1, which keeps the level of uncorrelated signals
+    #[id = "equal_power"]
+    #[name = "Equal Power"]
+    EqualPower,
+}
+
+impl MixLaw {
+    /// The gains of the dry and wet signals for a mix between 0 (dry) and 1 (wet).
+    fn gains(self, mix: f32) -> (f32, f32) {
+        match self {
+            MixLaw::Linear => (1.0 - mix, mix),
+            MixLaw::EqualPower => {
+                let angle = mix * std::f32::consts::FRAC_PI_2;
+                (angle.cos(), angle.sin())
+            }
+        }
+    }
 }
 
 #[derive(Enum, Clone, Copy, Debug, PartialEq, Eq)]
@@ -26,14 +150,46 @@ enum ProcessingMode {
     Multi,
 }
 
+#[derive(Enum, Clone, Copy, Debug, PartialEq, Eq)]
+enum TargetMode {
+    #[id = "notes"]
+    Notes,
+    #[id = "hum"]
+    Hum,
+    #[id = "list"]
+    #[name = "Frequency List"]
+    List,
+}
+
+#[derive(Enum, Clone, Copy, Debug, PartialEq, Eq)]
+enum NoteSource {
+    #[id = "notes"]
+    Notes,
+    #[id = "chord"]
+    Chord,
+    #[id = "sequence"]
+    Sequence,
+}
+
 #[derive(Params)]
 struct ColourizerRsParams {
+    /// The editor's window size.
+    #[persist = "editor-state"]
+    editor_state: Arc<EguiState>,
+
     /// The parameter's ID is used to identify the parameter in the wrappred plugin API. As long as
     /// these IDs remain constant, you can rename and reorder these fields as you wish. The
     /// parameters are exposed to the host in the same order they were defined. In this case, this
     /// gain parameter is stored as linear gain while the values are displayed in decibels.
     #[id = "gain"]
     pub gain: FloatParam,
+    /// Gain applied to the input before it reaches the filter bank, setting how hard the
+    /// resonances are driven
+    #[id = "input_trim"]
+    pub input_trim: FloatParam,
+    /// Gain applied after the dry/wet mix
+    #[id = "output_gain"]
+    pub output_gain: FloatParam,
     #[id = "c"]
     pub c: FloatParam,
     #[id = "c_sharp"]
@@ -58,9 +214,141 @@ struct ColourizerRsParams {
     pub a_sharp: FloatParam,
     #[id = "b"]
     pub b: FloatParam,
+    /// Scale preset. Anything other than `Custom` replaces the twelve note parameters.
+    #[id = "scale"]
+    pub scale: EnumParam<Scale>,
+    /// Root note the scale preset is rotated to
+    #[id = "root"]
+    pub root: EnumParam<Root>,
+    /// Whether the note gains come from the note parameters or from the chord list
+    #[id = "source"]
+    pub source: EnumParam<NoteSource>,
+    /// One-based index into `chords` used in chord mode
+    #[id = "chord"]
+    pub chord: IntParam,
+    #[id = "chord_root"]
+    pub chord_root: FloatParam,
+    #[id = "chord_third"]
+    pub chord_third: FloatParam,
+    #[id = "chord_fifth"]
+    pub chord_fifth: FloatParam,
+    #[id = "chord_ext"]
+    pub chord_extensions: FloatParam,
+    /// Chord symbols selectable with the chord parameter, stored in the plugin state
+    #[persist = "chords"]
+    pub chords: RwLock<Vec<String>>,
+    /// Length of the crossfade into the next sequencer step, in beats
+    #[id = "seq_xfade"]
+    pub seq_crossfade: FloatParam,
+    /// The sequencer's steps, stored in the plugin state
+    #[persist = "pattern"]
+    pub pattern: RwLock<Pattern>,
+    /// The second note mask. The note source above acts as mask A.
+    #[nested(id_prefix = "mask_b", group = "Mask B")]
+    pub mask_b: NoteMaskParams,
+    /// Interpolates the note gains from mask A (0) to mask B (1)
+    #[id = "morph"]
+    pub morph: FloatParam,
+    /// Cut the enabled notes from the input instead of picking them out
+    #[id = "invert"]
+    pub invert: BoolParam,
+    /// Whether the filters are centred on the notes, a hum series or the frequency list
+    #[id = "target"]
+    pub target: EnumParam<TargetMode>,
+    /// Where the hum's fundamental comes from
+    #[id = "mains"]
+    pub mains: EnumParam<Mains>,
+    /// The hum's fundamental when the mains parameter is set to custom
+    #[id = "hum_freq"]
+    pub hum_frequency: FloatParam,
+    /// Number of filters in the hum series, including the fundamental
+    #[id = "hum_harmonics"]
+    pub hum_harmonics: IntParam,
+    /// The mains frequency found by the auto detection
+    #[persist = "detected_mains"]
+    pub detected_mains: RwLock<Option<f32>>,
+    /// Resonances with their own gain and `Q`, used when targeting the frequency list
+    #[persist = "frequency_list"]
+    pub frequency_list: RwLock<FrequencyList>,
+    /// Whether the notes are picked out by the filter bank or by the spectral engine
+    #[id = "engine"]
+    pub engine: EnumParam<Engine>,
+    /// Width of the spectral engine's pass bands in cents
+    #[id = "bin_width"]
+    pub bin_width: FloatParam,
+    /// Time for the resonances to decay by 60 dB. Zero keeps the filters' natural decay.
+    #[id = "decay"]
+    pub decay: FloatParam,
+    /// Ignore the input and keep the current resonances ringing
+    #[id = "freeze"]
+    pub freeze: BoolParam,
+    /// How many of the 3rd, 5th and 7th harmonics each enabled note excites
+    #[id = "harmonics"]
+    pub harmonics: IntParam,
+    /// Level of each harmonic relative to the previous one
+    #[id = "harm_rolloff"]
+    pub harmonic_rolloff: FloatParam,
+    /// Stretches the harmonics upwards like a stiff piano string
+    #[id = "inharmonicity"]
+    pub inharmonicity: FloatParam,
+    /// Per-note offsets of the filters' centre frequencies
+    #[nested(id_prefix = "detune", group = "Detune")]
+    pub detune: DetuneParams,
+    /// Stretched tuning following the Railsback curve, 1 matches a typical piano
+    #[id = "stretch"]
+    pub stretch: FloatParam,
+    /// Frequency of A4 the filter bank is tuned to
+    #[id = "reference"]
+    pub reference: FloatParam,
+    /// Analyze the input and estimate how far it deviates from A4 = 440 Hz
+    #[id = "learn_tuning"]
+    pub learn_tuning: BoolParam,
+    /// Offset the reference by the detected tuning
+    #[id = "apply_tuning"]
+    pub apply_tuning: BoolParam,
+    /// The last detected tuning offset in cents, stored in the plugin state so hosts and editors
+    /// can read it back
+    #[persist = "detected_tuning"]
+    pub detected_tuning: RwLock<Option<f32>>,
+    /// Oversampling factor of the filter bank
+    #[id = "quality"]
+    pub quality: EnumParam<Quality>,
+    /// Saturation curve applied to the resonances
+    #[id = "character"]
+    pub character: EnumParam<Character>,
+    /// How hard the resonances are driven into the saturation
+    #[id = "drive"]
+    pub drive: FloatParam,
+    /// How much of the input's transients bypass the filter bank
+    #[id = "transient_keep"]
+    pub transient_keep: FloatParam,
+    /// Match the wet signal's loudness to the dry signal
+    #[id = "auto_gain"]
+    pub auto_gain: BoolParam,
+    /// Time constant of the auto gain's level detectors
+    #[id = "auto_gain_time"]
+    pub auto_gain_time: FloatParam,
+    /// Lookahead limiter on the wet signal. Enabling it adds a small amount of latency.
+    #[id = "limiter"]
+    pub limiter: BoolParam,
+    /// The limiter's ceiling in decibels
+    #[id = "ceiling"]
+    pub ceiling: FloatParam,
     /// Dry/wet mix between 0 (dry) and 1 (wet)
     #[id = "dry_wet"]
     pub dry_wet: FloatParam,
+    #[id = "mix_law"]
+    pub mix_law: EnumParam<MixLaw>,
+    /// Pass the dry signal through all-pass filters that follow the phase of the filter bank,
+    /// so the two don't partially cancel when they're mixed
+    #[id = "phase_align"]
+    pub phase_align: BoolParam,
+    /// Crossfades to the delayed dry signal. The processing keeps running while bypassed.
+    #[id = "bypass"]
+    pub bypass: BoolParam,
+    /// Length of the bypass crossfade in milliseconds
+    #[id = "bypass_time"]
+    pub bypass_time: FloatParam,
     /// Processing mode: mono or multi-channel
     #[id = "mode"]
     pub mode: EnumParam<ProcessingMode>,
@@ -69,11 +357,34 @@ struct ColourizerRsParams {
 impl Default for ColourizerRs {
     fn default() -> Self {
         let sample_rate = 44_100.0;
+        let (response, response_output) = ResponsePublisher::new(sample_rate);
+        let (analyzer, analysis_output) = Analyzer::new(sample_rate);
         Self {
             params: Arc::new(ColourizerRsParams::default()),
-            filterbank: FilterBank::new(sample_rate),
-            filterbanks: Vec::new(),
+            wet: WetPath::new(sample_rate),
+            wet_paths: Vec::new(),
+            dry_delays: Vec::new(),
+            dry_phase: Vec::new(),
+            latency: 0,
+            gain_reduction: Arc::new(AtomicF32::new(0.0)),
+            response,
+            response_output: Arc::new(Mutex::new(response_output)),
+            analyzer,
+            analysis_output: Arc::new(Mutex::new(analysis_output)),
             sample_rate,
+            note_gains: [0.0; 12],
+            note_gain_values: Vec::new(),
+            morph_values: Vec::new(),
+            input_trim_values: Vec::new(),
+            output_gain_values: Vec::new(),
+            bypass_position: 0.0,
+            bypass_values: Vec::new(),
+            residual: Vec::new(),
+            stems: Vec::new(),
+            aux_output: AuxOutput::None,
+            tuning_estimator: TuningEstimator::new(sample_rate),
+            mains_detector: MainsDetector::new(sample_rate),
+            learning: false,
         }
     }
 }
@@ -83,6 +394,8 @@ impl Default for ColourizerRsParams {
         const MIYAKO_BUSHI: [f32; 12] =
             [1.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0, 0.0, 0.0];
         Self {
+            editor_state: editor::default_state(),
+
             // This gain is stored as linear gain. NIH-plug comes with useful conversion functions
             // to treat these kinds of parameters as if we were dealing with decibels. Storing this
             // as decibels is easier to work with, but requires a conversion for every sample.
@@ -106,6 +419,32 @@ impl Default for ColourizerRsParams {
             // `.with_step_size(0.1)` function to get internal rounding.
             .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
             .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+            input_trim: FloatParam::new(
+                "Input Trim",
+                util::db_to_gain(0.0),
+                FloatRange::Skewed {
+                    min: util::db_to_gain(-24.0),
+                    max: util::db_to_gain(24.0),
+                    factor: FloatRange::gain_skew_factor(-24.0, 24.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
+            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+            output_gain: FloatParam::new(
+                "Output Gain",
+                util::db_to_gain(0.0),
+                FloatRange::Skewed {
+                    min: util::db_to_gain(-24.0),
+                    max: util::db_to_gain(24.0),
+                    factor: FloatRange::gain_skew_factor(-24.0, 24.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
+            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
             c: FloatParam::new(
                 "C",
                 MIYAKO_BUSHI[0],
@@ -166,12 +505,695 @@ impl Default for ColourizerRsParams {
                 MIYAKO_BUSHI[11],
                 FloatRange::Linear { min: 0.0, max: 1.0 },
             ),
+            scale: EnumParam::new("Scale", Scale::Custom),
+            root: EnumParam::new("Root", Root::C),
+            source: EnumParam::new("Note Source", NoteSource::Notes),
+            chord: IntParam::new(
+                "Chord",
+                1,
+                IntRange::Linear {
+                    min: 1,
+                    max: MAX_CHORDS,
+                },
+            ),
+            chord_root: FloatParam::new(
+                "Chord Root",
+                1.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            ),
+            chord_third: FloatParam::new(
+                "Chord Third",
+                1.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            ),
+            chord_fifth: FloatParam::new(
+                "Chord Fifth",
+                1.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            ),
+            chord_extensions: FloatParam::new(
+                "Chord Extensions",
+                1.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            ),
+            chords: RwLock::new(DEFAULT_CHORDS.iter().map(|s| s.to_string()).collect()),
+            seq_crossfade: FloatParam::new(
+                "Step Crossfade",
+                0.25,
+                FloatRange::Linear { min: 0.0, max: 4.0 },
+            )
+            .with_unit(" beats")
+            .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            pattern: RwLock::new(Pattern::default()),
+            mask_b: NoteMaskParams::new("Mask B", Scale::InSen.mask(Root::C).unwrap_or([0.0; 12])),
+            // Smoothed so sweeping between masks doesn't cause zipper noise
+            morph: FloatParam::new("Morph", 0.0, FloatRange::Linear { min: 0.0, max: 1.0 })
+                .with_smoother(SmoothingStyle::Linear(50.0)),
+            invert: BoolParam::new("Invert", false),
+            target: EnumParam::new("Target", TargetMode::Notes),
+            mains: EnumParam::new("Mains", Mains::Auto),
+            hum_frequency: FloatParam::new(
+                "Hum Frequency",
+                50.0,
+                FloatRange::Skewed {
+                    min: 20.0,
+                    max: 1000.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_unit(" Hz")
+            .with_step_size(0.01),
+            hum_harmonics: IntParam::new(
+                "Hum Harmonics",
+                8,
+                IntRange::Linear {
+                    min: 1,
+                    max: MAX_HUM_HARMONICS as i32,
+                },
+            ),
+            detected_mains: RwLock::new(None),
+            frequency_list: RwLock::new(FrequencyList::default()),
+            engine: EnumParam::new("Engine", Engine::Filters),
+            bin_width: FloatParam::new(
+                "Bin Width",
+                50.0,
+                FloatRange::Linear {
+                    min: 5.0,
+                    max: 100.0,
+                },
+            )
+            .with_unit(" ct")
+            .with_step_size(0.1),
+            decay: FloatParam::new(
+                "Decay",
+                0.0,
+                FloatRange::Skewed {
+                    min: 0.0,
+                    max: 30.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_value_to_string(Arc::new(|value| {
+                if value <= 0.0 {
+                    String::from("Natural")
+                } else {
+                    format!("{value:.2} s")
+                }
+            })),
+            freeze: BoolParam::new("Freeze", false),
+            harmonics: IntParam::new("Harmonics", 0, IntRange::Linear { min: 0, max: 3 }),
+            harmonic_rolloff: FloatParam::new(
+                "Harmonic Rolloff",
+                0.5,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            ),
+            inharmonicity: FloatParam::new(
+                "Inharmonicity",
+                0.0,
+                FloatRange::Skewed {
+                    min: 0.0,
+                    max: 0.01,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_value_to_string(formatters::v2s_f32_rounded(5)),
+            detune: DetuneParams::default(),
+            stretch: FloatParam::new("Stretch", 0.0, FloatRange::Linear { min: 0.0, max: 2.0 })
+                .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            reference: FloatParam::new(
+                "Reference",
+                440.0,
+                FloatRange::Linear {
+                    min: 415.0,
+                    max: 466.0,
+                },
+            )
+            .with_unit(" Hz")
+            .with_step_size(0.01),
+            learn_tuning: BoolParam::new("Learn Tuning", false),
+            apply_tuning: BoolParam::new("Apply Detected Tuning", false),
+            detected_tuning: RwLock::new(None),
+            quality: EnumParam::new("Quality", Quality::X1),
+            character: EnumParam::new("Character", Character::Clean),
+            drive: FloatParam::new(
+                "Drive",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 24.0,
+                },
+            )
+            .with_unit(" dB")
+            .with_step_size(0.1),
+            transient_keep: FloatParam::new(
+                "Transient Keep",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            ),
+            auto_gain: BoolParam::new("Auto Gain", false),
+            auto_gain_time: FloatParam::new(
+                "Auto Gain Time",
+                DEFAULT_AUTO_GAIN_TIME,
+                FloatRange::Skewed {
+                    min: 10.0,
+                    max: 5000.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(0)),
+            limiter: BoolParam::new("Limiter", false),
+            ceiling: FloatParam::new(
+                "Ceiling",
+                -0.3,
+                FloatRange::Linear {
+                    min: -24.0,
+                    max: 0.0,
+                },
+            )
+            .with_unit(" dB")
+            .with_step_size(0.1),
             dry_wet: FloatParam::new("Dry/Wet", 1.0, FloatRange::Linear { min: 0.0, max: 1.0 }),
+            mix_law: EnumParam::new("Mix Law", MixLaw::Linear),
+            phase_align: BoolParam::new("Phase Align", false),
+            bypass: BoolParam::new("Bypass", false).make_bypass(),
+            bypass_time: FloatParam::new(
+                "Bypass Crossfade",
+                DEFAULT_BYPASS_TIME,
+                FloatRange::Skewed {
+                    min: 1.0,
+                    max: 1000.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(0)),
             mode: EnumParam::new("Processing Mode", ProcessingMode::Mono),
         }
     }
 }
 
+impl ColourizerRsParams {
+    /// The per-note gains for C..B, taking the note source and scale preset into account.
+    /// Returns `None` if the chord list or pattern is currently being edited.
+    fn note_gains(&self, transport: &TransportInfo) -> Option<[f32; 12]> {
+        match self.source.value() {
+            NoteSource::Notes => Some(self.note_param_gains()),
+            NoteSource::Chord => self.chord_gains(),
+            NoteSource::Sequence => self.sequence_gains(transport),
+        }
+    }
+
+    /// The gains from the twelve note parameters or the selected scale preset.
+    fn note_param_gains(&self) -> [f32; 12] {
+        if let Some(mask) = self.scale.value().mask(self.root.value()) {
+            return mask;
+        }
+        self.note_params().map(FloatParam::value)
+    }
+
+    /// The twelve note parameters for C..B.
+    fn note_params(&self) -> [&FloatParam; 12] {
+        [
+            &self.c,
+            &self.c_sharp,
+            &self.d,
+            &self.d_sharp,
+            &self.e,
+            &self.f,
+            &self.f_sharp,
+            &self.g,
+            &self.g_sharp,
+            &self.a,
+            &self.a_sharp,
+            &self.b,
+        ]
+    }
+
+    /// The mask for the selected chord. Symbols that don't parse result in silence.
+    fn chord_gains(&self) -> Option<[f32; 12]> {
+        let weights = ChordWeights {
+            root: self.chord_root.value(),
+            third: self.chord_third.value(),
+            fifth: self.chord_fifth.value(),
+            extensions: self.chord_extensions.value(),
+        };
+        // Never block the audio thread while the list is being edited
+        let chords = self.chords.try_read().ok()?;
+        let index = (self.chord.value() - 1) as usize;
+        Some(
+            chords
+                .get(index)
+                .and_then(|symbol| parse_chord(symbol).ok())
+                .map_or([0.0; 12], |chord| chord.mask(weights)),
+        )
+    }
+
+    /// The reference frequency for A4. When enabled, the detected tuning replaces the
+    /// `Reference` parameter. It's measured against 440 Hz, not against the parameter.
+    fn reference_freq(&self) -> f32 {
+        let detected = if self.apply_tuning.value() {
+            self.detected_reference()
+        } else {
+            None
+        };
+        detected.unwrap_or_else(|| self.reference.value())
+    }
+
+    /// The frequency of A4 in the detected tuning, or `None` if nothing has been detected yet.
+    pub(crate) fn detected_reference(&self) -> Option<f32> {
+        self.detected_tuning
+            .try_read()
+            .ok()
+            .and_then(|cents| *cents)
+            .map(|cents| 440.0 * 2.0_f32.powf(cents / 1200.0))
+    }
+
+    /// What the filter bank is centred on. Until the auto detection has found anything, the hum
+    /// series assumes 50 Hz mains.
+    fn target(&self) -> Target {
+        match self.target.value() {
+            TargetMode::Notes => return Target::Notes,
+            TargetMode::List => return Target::List,
+            TargetMode::Hum => (),
+        }
+        let fundamental = match self.mains.value() {
+            Mains::Auto => self
+                .detected_mains
+                .try_read()
+                .ok()
+                .and_then(|mains| *mains)
+                .unwrap_or(50.0),
+            Mains::Hz50 => 50.0,
+            Mains::Hz60 => 60.0,
+            Mains::Custom => self.hum_frequency.value(),
+        };
+        Target::Hum(Hum {
+            fundamental,
+            harmonics: self.hum_harmonics.value() as usize,
+        })
+    }
+
+    /// The settings of the wet paths for the next block.
+    fn wet_settings(&self) -> WetSettings {
+        WetSettings {
+            engine: self.engine.value(),
+            bin_width: self.bin_width.value(),
+            invert: self.invert.value(),
+            target: self.target(),
+            decay: Some(self.decay.value()).filter(|d| *d > 0.0),
+            freeze: self.freeze.value(),
+            harmonics: Harmonics {
+                count: self.harmonics.value() as usize,
+                rolloff: self.harmonic_rolloff.value(),
+                inharmonicity: self.inharmonicity.value(),
+            },
+            tuning: Tuning {
+                reference: self.reference_freq(),
+                detune: self.detune.cents(),
+                stretch: self.stretch.value(),
+            },
+            oversampling: self.quality.value().factor(),
+            character: self.character.value(),
+            drive: self.drive.value(),
+            auto_gain: Some(self.auto_gain_time.value()).filter(|_| self.auto_gain.value()),
+            ceiling: Some(self.ceiling.value()).filter(|_| self.limiter.value()),
+            transient_keep: self.transient_keep.value(),
+            phase_align: self.phase_align.value(),
+        }
+    }
+
+    /// Fill `gains` with the note gains at every sample of a block that starts at `transport`.
+    /// Only the sequencer changes them within a block. Returns `false` and leaves `gains` alone
+    /// if the chord list or pattern is being edited.
+    fn fill_note_gains(
+        &self,
+        transport: &TransportInfo,
+        sample_rate: f32,
+        gains: &mut [[f32; 12]],
+    ) -> bool {
+        if self.source.value() != NoteSource::Sequence {
+            return match self.note_gains(transport) {
+                Some(note_gains) => {
+                    gains.fill(note_gains);
+                    true
+                }
+                None => false,
+            };
+        }
+        let pattern = match self.pattern.try_read() {
+            Ok(pattern) => pattern,
+            Err(_) => return false,
+        };
+        let crossfade = self.seq_crossfade.value() as f64;
+        for (n, out) in gains.iter_mut().enumerate() {
+            *out = pattern
+                .mask_at(&transport.advance(n, sample_rate), crossfade)
+                .unwrap_or([0.0; 12]);
+        }
+        true
+    }
+
+    /// The mask of the sequencer step at the host's current position. An empty pattern is silent.
+    fn sequence_gains(&self, transport: &TransportInfo) -> Option<[f32; 12]> {
+        let pattern = self.pattern.try_read().ok()?;
+        Some(
+            pattern
+                .mask_at(transport, self.seq_crossfade.value() as f64)
+                .unwrap_or([0.0; 12]),
+        )
+    }
+}
+
+impl ColourizerRs {
+    /// The latency of the enabled processing in samples.
+    fn latency(&self) -> u32 {
+        self.wet.latency()
+    }
+
+    fn dry_delay(&self) -> DelayLine {
+        DelayLine::new((self.sample_rate * MAX_LATENCY_SECONDS) as usize + spectral::FFT_SIZE)
+    }
+
+    /// Move the bypass crossfade through the next `num_samples` samples and store its
+    /// positions in `bypass_values`.
+    fn advance_bypass(&mut self, num_samples: usize) {
+        if self.bypass_values.len() < num_samples {
+            self.bypass_values.resize(num_samples, 0.0);
+        }
+        let target = if self.params.bypass.value() { 1.0 } else { 0.0 };
+        let fade_samples = self.params.bypass_time.value() / 1000.0 * self.sample_rate;
+        let step = 1.0 / fade_samples.max(1.0);
+        for value in &mut self.bypass_values[..num_samples] {
+            self.bypass_position = if target > self.bypass_position {
+                (self.bypass_position + step).min(target)
+            } else {
+                (self.bypass_position - step).max(target)
+            };
+            *value = self.bypass_position;
+        }
+    }
+
+    /// Hand the stored frequency list to the wet paths. The list is skipped while it's being
+    /// edited.
+    fn update_frequency_list(&mut self) {
+        if let Ok(list) = self.params.frequency_list.try_read() {
+            self.wet.set_frequency_list(&list.resonances);
+            for wet in &mut self.wet_paths {
+                wet.set_frequency_list(&list.resonances);
+            }
+        }
+    }
+
+    /// Process a block with the note sequencer following `transport`, so tests can drive it
+    /// without a host.
+    fn process_with_transport(
+        &mut self,
+        buffer: &mut Buffer,
+        aux: &mut AuxiliaryBuffers,
+        context: &mut impl ProcessContext<Self>,
+        transport: &TransportInfo,
+    ) -> ProcessStatus {
+        let num_samples = buffer.samples();
+        if self.note_gain_values.len() < num_samples {
+            self.note_gain_values.resize(num_samples, [0.0; 12]);
+        }
+        let note_gain_values = &mut self.note_gain_values[..num_samples];
+        if !self
+            .params
+            .fill_note_gains(transport, self.sample_rate, note_gain_values)
+        {
+            note_gain_values.fill(self.note_gains);
+        }
+        if let Some(last) = note_gain_values.last() {
+            self.note_gains = *last;
+        }
+        let note_gains = self.note_gains;
+        let mask_b = self.params.mask_b.gains();
+        let learn = self.params.learn_tuning.value();
+        if learn && !self.learning {
+            self.tuning_estimator.reset();
+        }
+        self.learning = learn;
+        let detect_mains = self.params.target.value() == TargetMode::Hum
+            && self.params.mains.value() == Mains::Auto;
+        let analyze = self.params.editor_state.is_open();
+        if learn || detect_mains || analyze {
+            for mut samples in buffer.iter_samples() {
+                let mut sum = 0.0;
+                for sample in samples.iter_mut() {
+                    sum += *sample;
+                }
+                let mono = sum / samples.len() as f32;
+                if learn {
+                    self.tuning_estimator.process_sample(mono);
+                }
+                if detect_mains {
+                    self.mains_detector.process_sample(mono);
+                }
+                if analyze {
+                    self.analyzer.push_input(mono);
+                }
+            }
+        }
+        if learn {
+            if let Some(cents) = self.tuning_estimator.estimate_cents() {
+                if let Ok(mut detected) = self.params.detected_tuning.try_write() {
+                    *detected = Some(cents);
+                }
+            }
+        }
+        if detect_mains {
+            if let Some(mains) = self.mains_detector.detected() {
+                if let Ok(mut detected) = self.params.detected_mains.try_write() {
+                    *detected = Some(mains);
+                }
+            }
+        }
+        let settings = self.params.wet_settings();
+        self.wet.configure(&settings);
+        if settings.target == Target::List {
+            self.update_frequency_list();
+        }
+        let latency = self.latency();
+        if latency != self.latency {
+            self.latency = latency;
+            context.set_latency_samples(latency);
+        }
+        let num_channels = buffer.channels();
+        if self.dry_delays.len() != num_channels {
+            self.dry_delays = vec![self.dry_delay(); num_channels];
+        }
+        for delay in &mut self.dry_delays {
+            delay.set_delay(latency as usize);
+        }
+        if self.dry_phase.len() != num_channels {
+            self.dry_phase = (0..num_channels)
+                .map(|_| PhaseCompensation::new(self.sample_rate))
+                .collect();
+        }
+        if self.residual.len() != num_channels
+            || self
+                .residual
+                .iter()
+                .any(|residual| residual.len() < num_samples)
+        {
+            self.residual = vec![vec![0.0; num_samples]; num_channels];
+        }
+        if self.stems.len() != num_channels
+            || self.stems.iter().any(|stems| stems.len() < num_samples)
+        {
+            self.stems = vec![vec![[0.0; 12]; num_samples]; num_channels];
+        }
+        self.advance_bypass(num_samples);
+        let split = self.aux_output == AuxOutput::Stems;
+        let mix_law = self.params.mix_law.value();
+        match self.params.mode.value() {
+            ProcessingMode::Mono => {
+                let (dry_gain, wet_gain) = mix_law.gains(self.params.dry_wet.value());
+                // The dry signal is compensated for the notes enabled at the start of the block
+                let morph_amount = self.params.morph.smoothed.previous_value();
+                let first_gains = self.note_gain_values.first().unwrap_or(&note_gains);
+                self.wet
+                    .set_gains(morph(first_gains, &mask_b, morph_amount));
+                for phase in &mut self.dry_phase {
+                    self.wet.update_phase_compensation(phase);
+                }
+                for (n, mut samples) in buffer.iter_samples().enumerate() {
+                    let gain = self.params.gain.smoothed.next();
+                    let input_trim = self.params.input_trim.smoothed.next();
+                    let output_gain = self.params.output_gain.smoothed.next();
+                    let morph_amount = self.params.morph.smoothed.next();
+                    self.wet
+                        .set_gains(morph(&self.note_gain_values[n], &mask_b, morph_amount));
+                    let mut sum = 0.0;
+                    for sample in samples.iter_mut() {
+                        sum += *sample;
+                    }
+                    let input_sum = sum / samples.len() as f32 * input_trim;
+                    let mut stems = [0.0; 12];
+                    let processed = if split {
+                        self.wet.process_sample_stems(input_sum, gain, &mut stems)
+                    } else {
+                        self.wet.process_sample(input_sum, gain)
+                    };
+                    let bypass = self.bypass_values[n];
+                    for (((sample, delay), phase), residual) in samples
+                        .iter_mut()
+                        .zip(self.dry_delays.iter_mut())
+                        .zip(self.dry_phase.iter_mut())
+                        .zip(self.residual.iter_mut())
+                    {
+                        let dry = delay.process(*sample);
+                        let aligned = phase.process_sample(dry * input_trim);
+                        let mixed = (aligned * dry_gain + processed * wet_gain) * output_gain;
+                        *sample = mixed * (1.0 - bypass) + dry * bypass;
+                        residual[n] = dry * input_trim - processed;
+                    }
+                    if split {
+                        for channel_stems in &mut self.stems {
+                            channel_stems[n] = stems;
+                        }
+                    }
+                }
+                self.gain_reduction
+                    .fetch_max(self.wet.take_gain_reduction_db(), Ordering::Relaxed);
+            }
+            ProcessingMode::Multi => {
+                if self.morph_values.len() < num_samples {
+                    self.morph_values.resize(num_samples, 0.0);
+                }
+                self.params
+                    .morph
+                    .smoothed
+                    .next_block(&mut self.morph_values, num_samples);
+                let morph_values = &self.morph_values[..num_samples];
+                if self.input_trim_values.len() < num_samples {
+                    self.input_trim_values.resize(num_samples, 0.0);
+                }
+                if self.output_gain_values.len() < num_samples {
+                    self.output_gain_values.resize(num_samples, 0.0);
+                }
+                self.params
+                    .input_trim
+                    .smoothed
+                    .next_block(&mut self.input_trim_values, num_samples);
+                self.params
+                    .output_gain
+                    .smoothed
+                    .next_block(&mut self.output_gain_values, num_samples);
+                let input_trim_values = &self.input_trim_values[..num_samples];
+                let output_gain_values = &self.output_gain_values[..num_samples];
+                let bypass_values = &self.bypass_values[..num_samples];
+                let note_gain_values = &self.note_gain_values[..num_samples];
+
+                let channels = buffer.as_slice();
+                if self.wet_paths.len() != channels.len() {
+                    self.wet_paths = (0..channels.len())
+                        .map(|_| WetPath::new(self.sample_rate))
+                        .collect();
+                }
+                for wet in &mut self.wet_paths {
+                    wet.configure(&settings);
+                }
+                let gain = self.params.gain.smoothed.next();
+                let (dry_gain, wet_gain) = mix_law.gains(self.params.dry_wet.value());
+                channels
+                    .par_iter_mut()
+                    .zip(self.wet_paths.par_iter_mut())
+                    .zip(self.dry_delays.par_iter_mut())
+                    .zip(self.dry_phase.par_iter_mut())
+                    .zip(self.residual.par_iter_mut())
+                    .zip(self.stems.par_iter_mut())
+                    .for_each(|(((((ch, wet), delay), phase), residual), stems)| {
+                        if let (Some(gains), Some(morph_amount)) =
+                            (note_gain_values.first(), morph_values.first())
+                        {
+                            wet.set_gains(morph(gains, &mask_b, *morph_amount));
+                        }
+                        wet.update_phase_compensation(phase);
+                        for (
+                            n,
+                            (
+                                ((((sample, morph_amount), input_trim), output_gain), bypass),
+                                residual,
+                            ),
+                        ) in ch
+                            .iter_mut()
+                            .zip(morph_values)
+                            .zip(input_trim_values)
+                            .zip(output_gain_values)
+                            .zip(bypass_values)
+                            .zip(residual.iter_mut())
+                            .enumerate()
+                        {
+                            wet.set_gains(morph(&note_gain_values[n], &mask_b, *morph_amount));
+                            let trimmed = *sample * input_trim;
+                            let processed = if split {
+                                wet.process_sample_stems(trimmed, gain, &mut stems[n])
+                            } else {
+                                wet.process_sample(trimmed, gain)
+                            };
+                            let dry = delay.process(*sample);
+                            let aligned = phase.process_sample(dry * input_trim);
+                            let mixed = (aligned * dry_gain + processed * wet_gain) * output_gain;
+                            *sample = mixed * (1.0 - bypass) + dry * bypass;
+                            *residual = dry * input_trim - processed;
+                        }
+                    });
+                let reduction = self
+                    .wet_paths
+                    .iter_mut()
+                    .map(WetPath::take_gain_reduction_db)
+                    .fold(0.0, f32::max);
+                self.gain_reduction.fetch_max(reduction, Ordering::Relaxed);
+            }
+        }
+
+        if analyze {
+            for mut samples in buffer.iter_samples() {
+                let mut sum = 0.0;
+                for sample in samples.iter_mut() {
+                    sum += *sample;
+                }
+                self.analyzer.push_output(sum / samples.len() as f32);
+            }
+            self.analyzer.set_reference(self.params.reference_freq());
+            self.analyzer.update(num_samples);
+
+            let wet = match self.params.mode.value() {
+                ProcessingMode::Mono => &self.wet,
+                ProcessingMode::Multi => self.wet_paths.first().unwrap_or(&self.wet),
+            };
+            let morph_amount = self.params.morph.smoothed.previous_value();
+            let gains = morph(&note_gains, &mask_b, morph_amount);
+            self.response.update(wet, &settings, gains, num_samples);
+        }
+
+        match self.aux_output {
+            AuxOutput::None => (),
+            AuxOutput::Residual => {
+                if let Some(output) = aux.outputs.first_mut() {
+                    for (out, residual) in output.as_slice().iter_mut().zip(self.residual.iter()) {
+                        out.copy_from_slice(&residual[..out.len()]);
+                    }
+                }
+            }
+            AuxOutput::Stems => {
+                for (class, output) in aux.outputs.iter_mut().enumerate().take(12) {
+                    for (out, stems) in output.as_slice().iter_mut().zip(self.stems.iter()) {
+                        for (sample, stems) in out.iter_mut().zip(stems.iter()) {
+                            *sample = stems[class];
+                        }
+                    }
+                }
+            }
+        }
+
+        ProcessStatus::Normal
+    }
+}
+
 impl Plugin for ColourizerRs {
     const NAME: &'static str = "Colourizer Rs";
     const VENDOR: &'static str = "Daishi Suzuki";
@@ -212,6 +1234,50 @@ impl Plugin for ColourizerRs {
                 ..PortNames::const_default()
             },
         },
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(2),
+            main_output_channels: NonZeroU32::new(2),
+            aux_input_ports: &[],
+            aux_output_ports: &[new_nonzero_u32(2)],
+            names: PortNames {
+                layout: Some("Stereo with Residual"),
+                aux_outputs: &["Residual"],
+                ..PortNames::const_default()
+            },
+        },
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(1),
+            main_output_channels: NonZeroU32::new(1),
+            aux_input_ports: &[],
+            aux_output_ports: &[new_nonzero_u32(1)],
+            names: PortNames {
+                layout: Some("Mono with Residual"),
+                aux_outputs: &["Residual"],
+                ..PortNames::const_default()
+            },
+        },
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(2),
+            main_output_channels: NonZeroU32::new(2),
+            aux_input_ports: &[],
+            aux_output_ports: &[new_nonzero_u32(2); 12],
+            names: PortNames {
+                layout: Some("Stereo with Stems"),
+                aux_outputs: &STEM_NAMES,
+                ..PortNames::const_default()
+            },
+        },
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(1),
+            main_output_channels: NonZeroU32::new(1),
+            aux_input_ports: &[],
+            aux_output_ports: &[new_nonzero_u32(1); 12],
+            names: PortNames {
+                layout: Some("Mono with Stems"),
+                aux_outputs: &STEM_NAMES,
+                ..PortNames::const_default()
+            },
+        },
     ];
 
     const MIDI_INPUT: MidiConfig = MidiConfig::None;
@@ -232,95 +1298,96 @@ impl Plugin for ColourizerRs {
         self.params.clone()
     }
 
+    fn editor(&mut self, _async_executor: AsyncExecutor<Self>) -> Option<Box<dyn Editor>> {
+        editor::create(
+            self.params.clone(),
+            self.params.editor_state.clone(),
+            self.response_output.clone(),
+            self.analysis_output.clone(),
+            self.gain_reduction.clone(),
+        )
+    }
+
     fn initialize(
         &mut self,
         audio_io_layout: &AudioIOLayout,
         buffer_config: &BufferConfig,
-        _context: &mut impl InitContext<Self>,
+        context: &mut impl InitContext<Self>,
     ) -> bool {
         self.sample_rate = buffer_config.sample_rate;
-        self.filterbank = FilterBank::new(self.sample_rate);
-        self.filterbanks = (0..audio_io_layout
+        self.note_gain_values = vec![[0.0; 12]; buffer_config.max_buffer_size as usize];
+        self.morph_values = vec![0.0; buffer_config.max_buffer_size as usize];
+        self.input_trim_values = vec![0.0; buffer_config.max_buffer_size as usize];
+        self.output_gain_values = vec![0.0; buffer_config.max_buffer_size as usize];
+        self.bypass_values = vec![0.0; buffer_config.max_buffer_size as usize];
+        self.tuning_estimator = TuningEstimator::new(self.sample_rate);
+        self.mains_detector = MainsDetector::new(self.sample_rate);
+        self.response.set_sample_rate(self.sample_rate);
+        self.analyzer.set_sample_rate(self.sample_rate);
+        let num_channels = audio_io_layout
             .main_output_channels
             .map(NonZeroU32::get)
-            .unwrap_or(0) as usize)
-            .map(|_| FilterBank::new(self.sample_rate))
+            .unwrap_or(0) as usize;
+        let settings = self.params.wet_settings();
+        self.wet = WetPath::new(self.sample_rate);
+        self.wet.configure(&settings);
+        self.wet_paths = (0..num_channels)
+            .map(|_| {
+                let mut wet = WetPath::new(self.sample_rate);
+                wet.configure(&settings);
+                wet
+            })
             .collect();
+        if let Ok(list) = self.params.frequency_list.read() {
+            if let Err(error) = list.validate(self.sample_rate) {
+                nih_log!("Frequency list: {error}");
+            }
+        }
+        self.update_frequency_list();
+        self.dry_delays = vec![self.dry_delay(); num_channels];
+        self.dry_phase = (0..num_channels)
+            .map(|_| PhaseCompensation::new(self.sample_rate))
+            .collect();
+        self.residual = vec![vec![0.0; buffer_config.max_buffer_size as usize]; num_channels];
+        self.stems = vec![vec![[0.0; 12]; buffer_config.max_buffer_size as usize]; num_channels];
+        self.aux_output = match audio_io_layout.aux_output_ports.len() {
+            0 => AuxOutput::None,
+            1 => AuxOutput::Residual,
+            _ => AuxOutput::Stems,
+        };
+        self.latency = self.latency();
+        context.set_latency_samples(self.latency);
         let _ = ThreadPoolBuilder::new().build_global();
         true
     }
 
     fn reset(&mut self) {
-        self.filterbank = FilterBank::new(self.sample_rate);
-        for fb in &mut self.filterbanks {
-            *fb = FilterBank::new(self.sample_rate);
+        self.wet.reset();
+        for wet in &mut self.wet_paths {
+            wet.reset();
         }
+        for delay in &mut self.dry_delays {
+            delay.reset();
+        }
+        for phase in &mut self.dry_phase {
+            phase.reset();
+        }
+        self.analyzer.reset();
+        self.bypass_position = if self.params.bypass.value() { 1.0 } else { 0.0 };
     }
 
     fn process(
         &mut self,
         buffer: &mut Buffer,
-        _aux: &mut AuxiliaryBuffers,
-        _context: &mut impl ProcessContext<Self>,
+        aux: &mut AuxiliaryBuffers,
+        context: &mut impl ProcessContext<Self>,
     ) -> ProcessStatus {
-        let note_gains = [
-            self.params.c.value(),
-            self.params.c_sharp.value(),
-            self.params.d.value(),
-            self.params.d_sharp.value(),
-            self.params.e.value(),
-            self.params.f.value(),
-            self.params.f_sharp.value(),
-            self.params.g.value(),
-            self.params.g_sharp.value(),
-            self.params.a.value(),
-            self.params.a_sharp.value(),
-            self.params.b.value(),
-        ];
-        match self.params.mode.value() {
-            ProcessingMode::Mono => {
-                self.filterbank.set_gains(note_gains);
-                let mix = self.params.dry_wet.value();
-                for mut samples in buffer.iter_samples() {
-                    let gain = self.params.gain.smoothed.next();
-                    let mut sum = 0.0;
-                    for sample in samples.iter_mut() {
-                        sum += *sample;
-                    }
-                    let input_sum = sum / samples.len() as f32;
-                    let processed = self.filterbank.process_sample(input_sum) * gain;
-                    for sample in samples.iter_mut() {
-                        let dry = *sample;
-                        *sample = dry * (1.0 - mix) + processed * mix;
-                    }
-                }
-            }
-            ProcessingMode::Multi => {
-                let channels = buffer.as_slice();
-                if self.filterbanks.len() != channels.len() {
-                    self.filterbanks = (0..channels.len())
-                        .map(|_| FilterBank::new(self.sample_rate))
-                        .collect();
-                }
-                for fb in &mut self.filterbanks {
-                    fb.set_gains(note_gains);
-                }
-                let gain = self.params.gain.smoothed.next();
-                let mix = self.params.dry_wet.value();
-                channels
-                    .par_iter_mut()
-                    .zip(self.filterbanks.par_iter_mut())
-                    .for_each(|(ch, fb)| {
-                        for sample in ch.iter_mut() {
-                            let dry = *sample;
-                            let wet = fb.process_sample(dry) * gain;
-                            *sample = dry * (1.0 - mix) + wet * mix;
-                        }
-                    });
-            }
-        }
-
-        ProcessStatus::Normal
+        // The transport is only queried when it's needed
+        let transport = match self.params.source.value() {
+            NoteSource::Sequence => TransportInfo::from_transport(context.transport()),
+            _ => TransportInfo::default(),
+        };
+        self.process_with_transport(buffer, aux, context, &transport)
     }
 }
 
@@ -355,6 +1422,7 @@ nih_export_vst3!(ColourizerRs);
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::sequencer::Step;
     use nih_plug::prelude::*;
 
     struct DummyContext;
@@ -380,15 +1448,47 @@ mod tests {
     fn plugin_with_mix(mix: f32) -> ColourizerRs {
         let mut params = ColourizerRsParams::default();
         params.dry_wet = FloatParam::new("Dry/Wet", mix, FloatRange::Linear { min: 0.0, max: 1.0 });
+        plugin_with_params(params)
+    }
+
+    fn plugin_with_params(params: ColourizerRsParams) -> ColourizerRs {
+        let mut wet = WetPath::new(44_100.0);
+        wet.configure(&params.wet_settings());
+        let (response, response_output) = ResponsePublisher::new(44_100.0);
+        let (analyzer, analysis_output) = Analyzer::new(44_100.0);
         ColourizerRs {
             params: Arc::new(params),
-            filterbank: FilterBank::new(44_100.0),
-            filterbanks: Vec::new(),
+            wet,
+            wet_paths: Vec::new(),
+            dry_delays: Vec::new(),
+            dry_phase: Vec::new(),
+            latency: 0,
+            gain_reduction: Arc::new(AtomicF32::new(0.0)),
+            response,
+            response_output: Arc::new(Mutex::new(response_output)),
+            analyzer,
+            analysis_output: Arc::new(Mutex::new(analysis_output)),
             sample_rate: 44_100.0,
+            note_gains: [0.0; 12],
+            note_gain_values: Vec::new(),
+            morph_values: Vec::new(),
+            input_trim_values: Vec::new(),
+            output_gain_values: Vec::new(),
+            bypass_position: 0.0,
+            bypass_values: Vec::new(),
+            residual: Vec::new(),
+            stems: Vec::new(),
+            aux_output: AuxOutput::None,
+            tuning_estimator: TuningEstimator::new(44_100.0),
+            mains_detector: MainsDetector::new(44_100.0),
+            learning: false,
         }
     }
 
     fn run_once(mut p: ColourizerRs) -> Vec<f32> {
+        let (input_trim, output_gain) = (p.params.input_trim.value(), p.params.output_gain.value());
+        p.params.input_trim.smoothed.reset(input_trim);
+        p.params.output_gain.smoothed.reset(output_gain);
         let mut data = vec![1.0; 16];
         let mut buffer = Buffer::default();
         unsafe {
@@ -431,4 +1531,592 @@ mod tests {
             assert!((*h - expected).abs() < 1e-6);
         }
     }
+
+    #[test]
+    fn default_notes_match_miyako_bushi_preset() {
+        let params = ColourizerRsParams::default();
+        assert_eq!(
+            params.note_gains(&TransportInfo::default()),
+            Scale::MiyakoBushi.mask(Root::C)
+        );
+    }
+
+    #[test]
+    fn scale_preset_overrides_notes() {
+        let params = ColourizerRsParams {
+            scale: EnumParam::new("Scale", Scale::Major),
+            root: EnumParam::new("Root", Root::G),
+            ..Default::default()
+        };
+        assert_eq!(
+            params.note_gains(&TransportInfo::default()),
+            Scale::Major.mask(Root::G)
+        );
+    }
+
+    fn chord_params(chord: i32) -> ColourizerRsParams {
+        ColourizerRsParams {
+            source: EnumParam::new("Note Source", NoteSource::Chord),
+            chord: IntParam::new(
+                "Chord",
+                chord,
+                IntRange::Linear {
+                    min: 1,
+                    max: MAX_CHORDS,
+                },
+            ),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn chord_source_uses_chord_list() {
+        // The second default chord is Am7: A C E G
+        assert_eq!(
+            chord_params(2).note_gains(&TransportInfo::default()),
+            Some([1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 1.0, 0.0, 0.0])
+        );
+
+        // Entries past the end of the list are silent
+        assert_eq!(
+            chord_params(MAX_CHORDS).note_gains(&TransportInfo::default()),
+            Some([0.0; 12])
+        );
+    }
+
+    #[test]
+    fn sequence_source_follows_transport() {
+        let params = ColourizerRsParams {
+            source: EnumParam::new("Note Source", NoteSource::Sequence),
+            seq_crossfade: FloatParam::new(
+                "Step Crossfade",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 4.0 },
+            ),
+            ..Default::default()
+        };
+        let pattern = Pattern::default();
+        // The default pattern plays one bar per chord, so beat 5 is in the second step
+        let transport = TransportInfo {
+            playing: true,
+            tempo: Some(120.0),
+            pos_beats: Some(5.0),
+            ..TransportInfo::default()
+        };
+        assert_eq!(params.note_gains(&transport), Some(pattern.steps[1].mask));
+    }
+
+    #[test]
+    fn sequence_steps_within_a_block() {
+        let mut a = [0.0; 12];
+        a[9] = 1.0;
+        // One beat of A followed by one beat of silence
+        let pattern = Pattern {
+            steps: vec![
+                Step {
+                    mask: a,
+                    beats: 1.0,
+                },
+                Step {
+                    mask: [0.0; 12],
+                    beats: 1.0,
+                },
+            ],
+        };
+        let sr = 44_100.0;
+        let input: Vec<f32> = (0..sr as usize)
+            .map(|n| (2.0 * std::f32::consts::PI * 440.0 * n as f32 / sr).sin())
+            .collect();
+        let rms = |samples: &[f32]| {
+            (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+        };
+        // At 120 BPM the whole block lasts two beats
+        let run = |playing: bool| {
+            let params = ColourizerRsParams {
+                source: EnumParam::new("Note Source", NoteSource::Sequence),
+                seq_crossfade: FloatParam::new(
+                    "Step Crossfade",
+                    0.0,
+                    FloatRange::Linear { min: 0.0, max: 4.0 },
+                ),
+                pattern: RwLock::new(pattern.clone()),
+                ..Default::default()
+            };
+            let transport = TransportInfo {
+                playing,
+                tempo: Some(120.0),
+                pos_beats: Some(0.0),
+                ..TransportInfo::default()
+            };
+            let mut p = plugin_with_params(params);
+            let (out, _) = run_with_transport(&mut p, &input, 0, &transport);
+            let quarter = out.len() / 4;
+            (rms(&out[quarter..2 * quarter]), rms(&out[3 * quarter..]))
+        };
+        let (first, second) = run(true);
+        assert!(first > 1.0, "{first}");
+        assert!(second < 0.01 * first, "{first} {second}");
+        // While the host is stopped the position doesn't move, so A keeps ringing
+        let (first, second) = run(false);
+        assert!(second > 0.5 * first, "{first} {second}");
+    }
+
+    #[test]
+    fn detected_tuning_offsets_reference() {
+        let params = ColourizerRsParams {
+            apply_tuning: BoolParam::new("Apply Detected Tuning", true),
+            detected_tuning: RwLock::new(Some(-1200.0)),
+            ..Default::default()
+        };
+        assert!((params.reference_freq() - 220.0).abs() < 1e-3);
+
+        // Nothing is applied until the toggle is enabled
+        let params = ColourizerRsParams {
+            detected_tuning: RwLock::new(Some(-1200.0)),
+            ..Default::default()
+        };
+        assert_eq!(params.reference_freq(), 440.0);
+        assert!((params.detected_reference().unwrap() - 220.0).abs() < 1e-3);
+        assert_eq!(ColourizerRsParams::default().detected_reference(), None);
+
+        // The detection is relative to 440 Hz, so a source at 432 Hz stays at 432 Hz whatever
+        // the reference is set to
+        let cents = 1200.0 * (432.0_f32 / 440.0).log2();
+        let reference = |value: f32| {
+            FloatParam::new(
+                "Reference",
+                value,
+                FloatRange::Linear {
+                    min: 415.0,
+                    max: 466.0,
+                },
+            )
+        };
+        let params = ColourizerRsParams {
+            reference: reference(432.0),
+            apply_tuning: BoolParam::new("Apply Detected Tuning", true),
+            detected_tuning: RwLock::new(Some(cents)),
+            ..Default::default()
+        };
+        assert!((params.reference_freq() - 432.0).abs() < 1e-3);
+        let params = ColourizerRsParams {
+            reference: reference(432.0),
+            apply_tuning: BoolParam::new("Apply Detected Tuning", true),
+            ..Default::default()
+        };
+        assert_eq!(params.reference_freq(), 432.0);
+    }
+
+    #[test]
+    fn hum_mode_follows_mains() {
+        assert_eq!(ColourizerRsParams::default().target(), Target::Notes);
+        let hum = |mains: Mains, detected: Option<f32>| {
+            let params = ColourizerRsParams {
+                target: EnumParam::new("Target", TargetMode::Hum),
+                mains: EnumParam::new("Mains", mains),
+                detected_mains: RwLock::new(detected),
+                ..Default::default()
+            };
+            match params.target() {
+                Target::Hum(hum) => hum.fundamental,
+                target => panic!("{target:?}"),
+            }
+        };
+        assert_eq!(hum(Mains::Auto, None), 50.0);
+        assert_eq!(hum(Mains::Auto, Some(60.0)), 60.0);
+        assert_eq!(hum(Mains::Hz50, Some(60.0)), 50.0);
+        assert_eq!(hum(Mains::Custom, None), 50.0);
+    }
+
+    #[test]
+    fn frequency_list_replaces_notes() {
+        let params = ColourizerRsParams {
+            target: EnumParam::new("Target", TargetMode::List),
+            frequency_list: RwLock::new(FrequencyList::parse("392 1 100").unwrap()),
+            ..Default::default()
+        };
+        let input = triad();
+        let (out, _) = run_with_aux(&mut plugin_with_params(params), &input, 0);
+        // G is the only listed frequency, and it's not in the default scale
+        assert!(out.iter().any(|s| s.abs() > 0.01));
+
+        let params = ColourizerRsParams {
+            target: EnumParam::new("Target", TargetMode::List),
+            ..Default::default()
+        };
+        let (out, _) = run_with_aux(&mut plugin_with_params(params), &input, 0);
+        // An empty list is silent
+        assert!(out.iter().all(|s| s.abs() < 1e-6));
+    }
+
+    #[test]
+    fn limiter_delays_dry_signal() {
+        let params = ColourizerRsParams {
+            dry_wet: FloatParam::new("Dry/Wet", 0.0, FloatRange::Linear { min: 0.0, max: 1.0 }),
+            limiter: BoolParam::new("Limiter", true),
+            ..Default::default()
+        };
+        let p = plugin_with_params(params);
+        assert_eq!(p.latency(), 66);
+        // The whole block is still inside the dry delay
+        for s in run_once(p) {
+            assert_eq!(s, 0.0);
+        }
+    }
+
+    /// Process `input` with `num_aux` auxiliary outputs and return the main and auxiliary
+    /// outputs.
+    fn run_with_aux(
+        p: &mut ColourizerRs,
+        input: &[f32],
+        num_aux: usize,
+    ) -> (Vec<f32>, Vec<Vec<f32>>) {
+        run_with_transport(p, input, num_aux, &TransportInfo::default())
+    }
+
+    /// Like `run_with_aux()`, with the host's transport at `transport` when the block starts.
+    fn run_with_transport(
+        p: &mut ColourizerRs,
+        input: &[f32],
+        num_aux: usize,
+        transport: &TransportInfo,
+    ) -> (Vec<f32>, Vec<Vec<f32>>) {
+        p.params.gain.smoothed.reset(1.0);
+        let (input_trim, output_gain) = (p.params.input_trim.value(), p.params.output_gain.value());
+        p.params.input_trim.smoothed.reset(input_trim);
+        p.params.output_gain.smoothed.reset(output_gain);
+        p.aux_output = match num_aux {
+            0 => AuxOutput::None,
+            1 => AuxOutput::Residual,
+            _ => AuxOutput::Stems,
+        };
+        let mut data = input.to_vec();
+        let mut aux_data = vec![vec![0.0; input.len()]; num_aux];
+        {
+            let mut buffer = Buffer::default();
+            let mut outputs: Vec<Buffer> = aux_data
+                .iter_mut()
+                .map(|data| {
+                    let mut buffer = Buffer::default();
+                    unsafe {
+                        buffer.set_slices(data.len(), |s| {
+                            *s = vec![data.as_mut_slice()];
+                        });
+                    }
+                    buffer
+                })
+                .collect();
+            unsafe {
+                buffer.set_slices(data.len(), |s| {
+                    *s = vec![data.as_mut_slice()];
+                });
+            }
+            let mut aux = AuxiliaryBuffers {
+                inputs: &mut [],
+                outputs: &mut outputs,
+            };
+            let mut ctx = DummyContext;
+            p.process_with_transport(&mut buffer, &mut aux, &mut ctx, transport);
+        }
+        (data, aux_data)
+    }
+
+    fn triad() -> Vec<f32> {
+        (0..2048)
+            .map(|n| {
+                let t = n as f32 / 44_100.0;
+                [261.63, 329.63, 392.0]
+                    .iter()
+                    .map(|f| 0.2 * (2.0 * std::f32::consts::PI * f * t).sin())
+                    .sum()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn residual_nulls_with_wet_signal() {
+        let input = triad();
+        for mode in [ProcessingMode::Mono, ProcessingMode::Multi] {
+            let params = ColourizerRsParams {
+                mode: EnumParam::new("Processing Mode", mode),
+                ..gain_staging_params(1.0, 1.0, 1.0)
+            };
+            let (wet, _) = run_with_aux(&mut plugin_with_params(params), &input, 1);
+            // The filter bank does pick something out of the input
+            assert!(wet.iter().any(|s| s.abs() > 0.01));
+            // The residual is taken before the mix, the output gain and the phase alignment
+            let params = ColourizerRsParams {
+                mode: EnumParam::new("Processing Mode", mode),
+                phase_align: BoolParam::new("Phase Align", true),
+                ..gain_staging_params(1.0, 2.0, 0.3)
+            };
+            let (out, aux) = run_with_aux(&mut plugin_with_params(params), &input, 1);
+            let residual = &aux[0];
+            assert!(out
+                .iter()
+                .zip(wet.iter())
+                .any(|(o, w)| (o - w).abs() > 0.01));
+            for ((w, r), x) in wet.iter().zip(residual.iter()).zip(input.iter()) {
+                assert!((w + r - x).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn residual_is_aligned_with_latency() {
+        let params = ColourizerRsParams {
+            limiter: BoolParam::new("Limiter", true),
+            ..Default::default()
+        };
+        let mut p = plugin_with_params(params);
+        let latency = p.latency() as usize;
+        let input = triad();
+        let (out, aux) = run_with_aux(&mut p, &input, 1);
+        let residual = &aux[0];
+        for n in 0..input.len() {
+            let delayed = if n >= latency {
+                input[n - latency]
+            } else {
+                0.0
+            };
+            assert!((out[n] + residual[n] - delayed).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn stems_add_up_to_wet_signal() {
+        let input = triad();
+        for mode in [ProcessingMode::Mono, ProcessingMode::Multi] {
+            let params = ColourizerRsParams {
+                mode: EnumParam::new("Processing Mode", mode),
+                ..Default::default()
+            };
+            let (out, stems) = run_with_aux(&mut plugin_with_params(params), &input, 12);
+            // Of the triad only C is in the default Miyako-bushi scale
+            assert!(stems[0].iter().any(|s| s.abs() > 0.01));
+            for (n, o) in out.iter().enumerate() {
+                let sum: f32 = stems.iter().map(|stem| stem[n]).sum();
+                assert!((sum - o).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn latency_follows_settings() {
+        assert_eq!(
+            plugin_with_params(ColourizerRsParams::default()).latency(),
+            0
+        );
+        let params = ColourizerRsParams {
+            limiter: BoolParam::new("Limiter", true),
+            quality: EnumParam::new("Quality", Quality::X2),
+            ..Default::default()
+        };
+        assert_eq!(plugin_with_params(params).latency(), 66 + 31);
+    }
+
+    fn bypass_params(bypass: bool) -> ColourizerRsParams {
+        ColourizerRsParams {
+            bypass: BoolParam::new("Bypass", bypass).make_bypass(),
+            bypass_time: FloatParam::new(
+                "Bypass Crossfade",
+                10.0,
+                FloatRange::Linear {
+                    min: 1.0,
+                    max: 1000.0,
+                },
+            ),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn bypass_passes_delayed_input() {
+        let input = triad();
+        for mode in [ProcessingMode::Mono, ProcessingMode::Multi] {
+            let params = ColourizerRsParams {
+                limiter: BoolParam::new("Limiter", true),
+                mode: EnumParam::new("Processing Mode", mode),
+                ..bypass_params(true)
+            };
+            let mut p = plugin_with_params(params);
+            // Plugins that are bypassed when they're activated start out bypassed
+            p.reset();
+            let latency = p.latency() as usize;
+            let (out, _) = run_with_aux(&mut p, &input, 0);
+            for n in 0..input.len() {
+                let delayed = if n >= latency {
+                    input[n - latency]
+                } else {
+                    0.0
+                };
+                assert_eq!(out[n], delayed);
+            }
+        }
+    }
+
+    #[test]
+    fn bypass_keeps_filters_running() {
+        let input = triad();
+        let mut reference = plugin_with_params(bypass_params(false));
+        let mut p = plugin_with_params(bypass_params(true));
+        p.reset();
+        run_with_aux(&mut reference, &input, 0);
+        run_with_aux(&mut p, &input, 0);
+
+        p.params = Arc::new(bypass_params(false));
+        let (expected, _) = run_with_aux(&mut reference, &input, 0);
+        let (out, _) = run_with_aux(&mut p, &input, 0);
+        // The crossfade takes 441 samples, after which the output is the same as if the plugin
+        // had never been bypassed
+        let halfway = 220;
+        let mixed = (input[halfway] + expected[halfway]) / 2.0;
+        assert!((out[halfway] - mixed).abs() < 0.01 * input[halfway].abs().max(0.1));
+        for n in 441..input.len() {
+            assert!((out[n] - expected[n]).abs() < 1e-6, "{n}");
+        }
+    }
+
+    #[test]
+    fn bypass_fade_keeps_phase_aligned_level() {
+        // A sine at the C5 peak, where the wet signal is in phase with the input
+        let sr = 44_100.0;
+        let input: Vec<f32> = (0..sr as usize)
+            .map(|n| 0.1 * (2.0 * std::f32::consts::PI * 523.25 * n as f32 / sr).sin())
+            .collect();
+        let params = |bypass: bool| ColourizerRsParams {
+            phase_align: BoolParam::new("Phase Align", true),
+            bypass_time: FloatParam::new(
+                "Bypass Crossfade",
+                1000.0,
+                FloatRange::Linear {
+                    min: 1.0,
+                    max: 1000.0,
+                },
+            ),
+            ..bypass_params(bypass)
+        };
+        let rms = |samples: &[f32]| {
+            (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+        };
+        let mut p = plugin_with_params(params(false));
+        let (out, _) = run_with_aux(&mut p, &input, 0);
+        let wet = rms(&out[out.len() - 4410..]);
+        let dry = rms(&input);
+        assert!(wet > 0.01);
+
+        // Halfway through the crossfade the two add up instead of cancelling out
+        p.params = Arc::new(params(true));
+        let (out, _) = run_with_aux(&mut p, &input, 0);
+        let halfway = rms(&out[20_000..24_000]);
+        let expected = (wet + dry) / 2.0;
+        assert!(
+            (halfway - expected).abs() < 0.1 * expected,
+            "{halfway} {expected}"
+        );
+    }
+
+    fn gain_staging_params(input_trim: f32, output_gain: f32, mix: f32) -> ColourizerRsParams {
+        let level = |name: &str, value: f32| {
+            FloatParam::new(
+                name,
+                value,
+                FloatRange::Linear {
+                    min: util::db_to_gain(-24.0),
+                    max: util::db_to_gain(24.0),
+                },
+            )
+        };
+        ColourizerRsParams {
+            input_trim: level("Input Trim", input_trim),
+            output_gain: level("Output Gain", output_gain),
+            dry_wet: FloatParam::new("Dry/Wet", mix, FloatRange::Linear { min: 0.0, max: 1.0 }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn output_gain_scales_mix() {
+        let input = triad();
+        let (expected, _) = run_with_aux(
+            &mut plugin_with_params(gain_staging_params(1.0, 1.0, 0.5)),
+            &input,
+            0,
+        );
+        let (out, _) = run_with_aux(
+            &mut plugin_with_params(gain_staging_params(1.0, 0.5, 0.5)),
+            &input,
+            0,
+        );
+        for (o, e) in out.iter().zip(expected.iter()) {
+            assert!((o - 0.5 * e).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn input_trim_drives_filters() {
+        let input = triad();
+        let (expected, _) = run_with_aux(
+            &mut plugin_with_params(gain_staging_params(1.0, 1.0, 1.0)),
+            &input,
+            0,
+        );
+        // The filters are linear, so trimming the input scales the wet signal
+        let (out, _) = run_with_aux(
+            &mut plugin_with_params(gain_staging_params(2.0, 1.0, 1.0)),
+            &input,
+            0,
+        );
+        assert!(expected.iter().any(|e| e.abs() > 0.01));
+        for (o, e) in out.iter().zip(expected.iter()) {
+            assert!((o - 2.0 * e).abs() < 1e-5);
+        }
+        // Trimming the input and making up for it at the output leaves the dry signal alone
+        let (dry, _) = run_with_aux(
+            &mut plugin_with_params(gain_staging_params(2.0, 0.5, 0.0)),
+            &input,
+            0,
+        );
+        for (d, i) in dry.iter().zip(input.iter()) {
+            assert!((d - i).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn equal_power_mix_keeps_level() {
+        let params = ColourizerRsParams {
+            dry_wet: FloatParam::new("Dry/Wet", 0.5, FloatRange::Linear { min: 0.0, max: 1.0 }),
+            mix_law: EnumParam::new("Mix Law", MixLaw::EqualPower),
+            ..Default::default()
+        };
+        // The filter bank blocks DC, so only the dry signal is left
+        for s in run_once(plugin_with_params(params)) {
+            assert!((s - std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-4);
+        }
+        assert_eq!(MixLaw::EqualPower.gains(0.0), (1.0, 0.0));
+        assert_eq!(MixLaw::Linear.gains(0.25), (0.75, 0.25));
+    }
+
+    #[test]
+    fn phase_align_fills_out_blend() {
+        // A sine on the skirt of the C5 peak, where the wet signal lags the dry one by about
+        // 70 degrees
+        let sr = 44_100.0;
+        let input: Vec<f32> = (0..3 * sr as usize)
+            .map(|n| (2.0 * std::f32::consts::PI * 525.73 * n as f32 / sr).sin())
+            .collect();
+        let level = |phase_align: bool| {
+            let params = ColourizerRsParams {
+                dry_wet: FloatParam::new("Dry/Wet", 0.5, FloatRange::Linear { min: 0.0, max: 1.0 }),
+                phase_align: BoolParam::new("Phase Align", phase_align),
+                ..Default::default()
+            };
+            let (out, _) = run_with_aux(&mut plugin_with_params(params), &input, 0);
+            let tail = &out[2 * sr as usize..];
+            (tail.iter().map(|s| s * s).sum::<f32>() / tail.len() as f32).sqrt()
+        };
+        let plain = level(false);
+        let aligned = level(true);
+        assert!(aligned > 1.05 * plain, "{aligned} {plain}");
+    }
 }
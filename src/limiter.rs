@@ -0,0 +1,192 @@
+// Lookahead safety limiter for the wet signal
+
+/// How far the limiter looks ahead. This is also the latency it adds.
+const LOOKAHEAD_MS: f32 = 1.5;
+/// Time for the gain to recover after a peak.
+const RELEASE_MS: f32 = 50.0;
+
+/// A delay of a whole number of samples, up to the maximum it was created with.
+#[derive(Clone, Debug)]
+pub struct DelayLine {
+    buffer: Vec<f32>,
+    pos: usize,
+    delay: usize,
+}
+
+impl DelayLine {
+    /// Create a delay line that delays by `max_delay` samples.
+    pub fn new(max_delay: usize) -> Self {
+        Self {
+            buffer: vec![0.0; max_delay + 1],
+            pos: 0,
+            delay: max_delay,
+        }
+    }
+
+    pub fn delay(&self) -> usize {
+        self.delay
+    }
+
+    /// Change the delay. This is clamped to the maximum delay.
+    pub fn set_delay(&mut self, delay: usize) {
+        self.delay = delay.min(self.buffer.len() - 1);
+    }
+
+    pub fn reset(&mut self) {
+        self.buffer.fill(0.0);
+        self.pos = 0;
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        let len = self.buffer.len();
+        self.buffer[self.pos] = input;
+        let output = self.buffer[(self.pos + len - self.delay) % len];
+        self.pos = (self.pos + 1) % len;
+        output
+    }
+}
+
+/// Brickwall limiter with a short lookahead. The gain needed to keep every sample below the
+/// ceiling is held for the length of the lookahead window and then averaged over the same
+/// window, so the gain has fully ramped down by the time a peak leaves the delay line.
+#[derive(Clone, Debug)]
+pub struct Limiter {
+    ceiling: f32,
+    release_coeff: f32,
+    delay: DelayLine,
+    /// Gains required by the samples in the lookahead window
+    targets: Vec<f32>,
+    /// Held gains that are averaged to get the applied gain
+    held: Vec<f32>,
+    held_sum: f64,
+    pos: usize,
+    release: f32,
+    /// The lowest gain applied since the gain reduction was last read
+    min_gain: f32,
+}
+
+impl Limiter {
+    pub fn new(sample_rate: f32) -> Self {
+        let latency = (LOOKAHEAD_MS * sample_rate / 1000.0).round() as usize;
+        let window = latency + 1;
+        Self {
+            ceiling: 1.0,
+            release_coeff: (-1000.0 / (RELEASE_MS * sample_rate)).exp(),
+            delay: DelayLine::new(latency),
+            targets: vec![1.0; window],
+            held: vec![1.0; window],
+            held_sum: window as f64,
+            pos: 0,
+            release: 1.0,
+            min_gain: 1.0,
+        }
+    }
+
+    /// The delay added by the limiter in samples.
+    pub fn latency(&self) -> u32 {
+        self.delay.delay() as u32
+    }
+
+    /// Set the ceiling in decibels.
+    pub fn set_ceiling_db(&mut self, ceiling_db: f32) {
+        self.ceiling = 10.0_f32.powf(ceiling_db / 20.0);
+    }
+
+    pub fn reset(&mut self) {
+        self.delay.reset();
+        self.targets.fill(1.0);
+        self.held.fill(1.0);
+        self.held_sum = self.held.len() as f64;
+        self.pos = 0;
+        self.release = 1.0;
+        self.min_gain = 1.0;
+    }
+
+    pub fn process_sample(&mut self, input: f32) -> f32 {
+        let magnitude = input.abs();
+        self.targets[self.pos] = if magnitude > self.ceiling {
+            self.ceiling / magnitude
+        } else {
+            1.0
+        };
+        let held = self.targets.iter().fold(1.0_f32, |a, b| a.min(*b));
+        self.release = if held < self.release {
+            held
+        } else {
+            held + self.release_coeff * (self.release - held)
+        };
+
+        self.held_sum += (self.release - self.held[self.pos]) as f64;
+        self.held[self.pos] = self.release;
+        self.pos = (self.pos + 1) % self.held.len();
+        let gain = ((self.held_sum / self.held.len() as f64) as f32).min(1.0);
+        self.min_gain = self.min_gain.min(gain);
+
+        // The clamp only catches rounding errors in the running average
+        (self.delay.process(input) * gain).clamp(-self.ceiling, self.ceiling)
+    }
+
+    /// The largest gain reduction in decibels since this was last called.
+    pub fn take_gain_reduction_db(&mut self) -> f32 {
+        let reduction = -20.0 * self.min_gain.max(1e-6).log10();
+        self.min_gain = 1.0;
+        reduction
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    fn sine(n: usize, amplitude: f32) -> f32 {
+        amplitude * (2.0 * PI * 440.0 * n as f32 / 44100.0).sin()
+    }
+
+    #[test]
+    fn test_delay_line() {
+        let mut delay = DelayLine::new(3);
+        let out: Vec<f32> = (1..=5).map(|x| delay.process(x as f32)).collect();
+        assert_eq!(out, [0.0, 0.0, 0.0, 1.0, 2.0]);
+        assert_eq!(DelayLine::new(0).process(1.0), 1.0);
+
+        let mut delay = DelayLine::new(4);
+        delay.set_delay(1);
+        let out: Vec<f32> = (1..=3).map(|x| delay.process(x as f32)).collect();
+        assert_eq!(out, [0.0, 1.0, 2.0]);
+        delay.set_delay(10);
+        assert_eq!(delay.delay(), 4);
+    }
+
+    #[test]
+    fn test_peaks_stay_below_ceiling() {
+        let mut limiter = Limiter::new(44100.0);
+        limiter.set_ceiling_db(-6.0);
+        let ceiling = 10.0_f32.powf(-6.0 / 20.0);
+        for n in 0..44100 {
+            // A sudden jump in level must not get through either
+            let amplitude = if n < 22050 { 0.1 } else { 8.0 };
+            assert!(limiter.process_sample(sine(n, amplitude)).abs() <= ceiling);
+        }
+        let reduction = limiter.take_gain_reduction_db();
+        assert!((reduction - 24.0).abs() < 0.5, "{reduction}");
+        assert_eq!(limiter.take_gain_reduction_db(), 0.0);
+    }
+
+    #[test]
+    fn test_quiet_signal_is_only_delayed() {
+        let mut limiter = Limiter::new(44100.0);
+        let latency = limiter.latency() as usize;
+        assert_eq!(latency, 66);
+        for n in 0..4410 {
+            let out = limiter.process_sample(sine(n, 0.5));
+            let expected = if n >= latency {
+                sine(n - latency, 0.5)
+            } else {
+                0.0
+            };
+            assert!((out - expected).abs() < 1e-6);
+        }
+        assert_eq!(limiter.take_gain_reduction_db(), 0.0);
+    }
+}
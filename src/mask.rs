@@ -0,0 +1,109 @@
+// Note mask parameters and morphing between masks
+
+use nih_plug::prelude::*;
+
+/// A set of twelve per-note gains for C..B.
+#[derive(Params)]
+pub struct NoteMaskParams {
+    #[id = "c"]
+    pub c: FloatParam,
+    #[id = "c_sharp"]
+    pub c_sharp: FloatParam,
+    #[id = "d"]
+    pub d: FloatParam,
+    #[id = "d_sharp"]
+    pub d_sharp: FloatParam,
+    #[id = "e"]
+    pub e: FloatParam,
+    #[id = "f"]
+    pub f: FloatParam,
+    #[id = "f_sharp"]
+    pub f_sharp: FloatParam,
+    #[id = "g"]
+    pub g: FloatParam,
+    #[id = "g_sharp"]
+    pub g_sharp: FloatParam,
+    #[id = "a"]
+    pub a: FloatParam,
+    #[id = "a_sharp"]
+    pub a_sharp: FloatParam,
+    #[id = "b"]
+    pub b: FloatParam,
+}
+
+impl NoteMaskParams {
+    /// Create the twelve parameters. `prefix` is prepended to the note names so the parameters
+    /// can be told apart from the main note parameters in a generic host UI.
+    pub fn new(prefix: &str, gains: [f32; 12]) -> Self {
+        let param = |note: &str, gain: f32| {
+            FloatParam::new(
+                format!("{prefix} {note}"),
+                gain,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+        };
+        Self {
+            c: param("C", gains[0]),
+            c_sharp: param("C#", gains[1]),
+            d: param("D", gains[2]),
+            d_sharp: param("D#", gains[3]),
+            e: param("E", gains[4]),
+            f: param("F", gains[5]),
+            f_sharp: param("F#", gains[6]),
+            g: param("G", gains[7]),
+            g_sharp: param("G#", gains[8]),
+            a: param("A", gains[9]),
+            a_sharp: param("A#", gains[10]),
+            b: param("B", gains[11]),
+        }
+    }
+
+    /// The current gains for C..B.
+    pub fn gains(&self) -> [f32; 12] {
+        [
+            self.c.value(),
+            self.c_sharp.value(),
+            self.d.value(),
+            self.d_sharp.value(),
+            self.e.value(),
+            self.f.value(),
+            self.f_sharp.value(),
+            self.g.value(),
+            self.g_sharp.value(),
+            self.a.value(),
+            self.a_sharp.value(),
+            self.b.value(),
+        ]
+    }
+}
+
+/// Linearly interpolate between two masks. `amount` 0 gives `a`, 1 gives `b`.
+pub fn morph(a: &[f32; 12], b: &[f32; 12], amount: f32) -> [f32; 12] {
+    let mut out = [0.0; 12];
+    for (o, (a, b)) in out.iter_mut().zip(a.iter().zip(b.iter())) {
+        *o = a + (b - a) * amount;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gains_order() {
+        let mut gains = [0.0; 12];
+        gains[1] = 1.0;
+        gains[11] = 0.5;
+        assert_eq!(NoteMaskParams::new("B", gains).gains(), gains);
+    }
+
+    #[test]
+    fn test_morph_endpoints() {
+        let a = [1.0; 12];
+        let b = [0.0; 12];
+        assert_eq!(morph(&a, &b, 0.0), a);
+        assert_eq!(morph(&a, &b, 1.0), b);
+        assert_eq!(morph(&a, &b, 0.25), [0.75; 12]);
+    }
+}
@@ -0,0 +1,307 @@
+// Polyphase half-band oversampling
+
+use nih_plug::prelude::Enum;
+use std::f32::consts::PI;
+
+/// Number of taps of each polyphase branch. The half-band filters have `2 * TAPS - 1` taps.
+const TAPS: usize = 32;
+/// The delay of the branch that only contains the centre tap.
+const CENTER: usize = TAPS / 2 - 1;
+/// The highest supported oversampling factor.
+pub const MAX_FACTOR: usize = 8;
+
+/// The last `TAPS` samples of a signal. Every sample is stored twice so the history can always
+/// be read as one contiguous slice.
+#[derive(Clone, Debug)]
+struct History {
+    buffer: [f32; 2 * TAPS],
+    pos: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            buffer: [0.0; 2 * TAPS],
+            pos: 0,
+        }
+    }
+}
+
+impl History {
+    fn push(&mut self, sample: f32) {
+        self.pos = (self.pos + 1) % TAPS;
+        self.buffer[self.pos] = sample;
+        self.buffer[self.pos + TAPS] = sample;
+    }
+
+    /// The sample from `delay` samples ago.
+    fn get(&self, delay: usize) -> f32 {
+        self.buffer[self.pos + TAPS - delay]
+    }
+
+    /// Convolve the history with `coefficients`, where `coefficients[i]` is applied to the
+    /// sample from `i` samples ago.
+    fn dot(&self, coefficients: &[f32; TAPS]) -> f32 {
+        let samples = &self.buffer[self.pos + 1..=self.pos + TAPS];
+        coefficients
+            .iter()
+            .zip(samples.iter().rev())
+            .map(|(c, x)| c * x)
+            .sum()
+    }
+}
+
+/// One doubling of the sample rate. The half-band filter's odd branch is a pure delay, so only
+/// the even branch needs to be convolved.
+#[derive(Clone, Debug)]
+struct HalfbandStage {
+    coefficients: [f32; TAPS],
+    up: History,
+    down_even: History,
+    down_odd: History,
+}
+
+impl HalfbandStage {
+    fn new() -> Self {
+        // Blackman windowed sinc with a cutoff at a quarter of the high sample rate. Only the
+        // odd taps around the centre are non-zero.
+        let length = 2 * TAPS - 1;
+        let mut coefficients = [0.0; TAPS];
+        for (i, c) in coefficients.iter_mut().enumerate() {
+            let k = 2.0 * i as f32 - (TAPS - 1) as f32;
+            let phase = 2.0 * PI * (2 * i) as f32 / (length - 1) as f32;
+            let window = 0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos();
+            *c = (PI * k / 2.0).sin() / (PI * k) * window;
+        }
+        // Normalize to unity gain at DC. The centre tap contributes the other half.
+        let sum: f32 = coefficients.iter().sum();
+        for c in &mut coefficients {
+            *c *= 0.5 / sum;
+        }
+
+        Self {
+            coefficients,
+            up: History::default(),
+            down_even: History::default(),
+            down_odd: History::default(),
+        }
+    }
+
+    fn upsample(&mut self, input: f32) -> [f32; 2] {
+        self.up.push(input);
+        [2.0 * self.up.dot(&self.coefficients), self.up.get(CENTER)]
+    }
+
+    fn downsample(&mut self, input: [f32; 2]) -> f32 {
+        self.down_even.push(input[0]);
+        self.down_odd.push(input[1]);
+        self.down_even.dot(&self.coefficients) + 0.5 * self.down_odd.get(CENTER + 1)
+    }
+}
+
+/// Oversampling factor, exposed as a quality setting.
+#[derive(Enum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Quality {
+    #[id = "1x"]
+    #[name = "1x"]
+    X1,
+    #[id = "2x"]
+    #[name = "2x"]
+    X2,
+    #[id = "4x"]
+    #[name = "4x"]
+    X4,
+    #[id = "8x"]
+    #[name = "8x"]
+    X8,
+}
+
+impl Quality {
+    pub fn factor(self) -> usize {
+        match self {
+            Quality::X1 => 1,
+            Quality::X2 => 2,
+            Quality::X4 => 4,
+            Quality::X8 => 8,
+        }
+    }
+}
+
+/// Runs a per-sample process at 1, 2, 4 or 8 times the sample rate by cascading half-band
+/// stages.
+#[derive(Clone, Debug)]
+pub struct Oversampler {
+    /// Stages for the highest factor are always allocated so the factor can be changed on the
+    /// audio thread
+    stages: Vec<HalfbandStage>,
+    num_stages: usize,
+    /// The stages delay by fractions of a sample from 4x up. The oversampled signal is delayed
+    /// by this many samples at the high rate so the latency is a whole number of samples.
+    padding: usize,
+    /// The last `factor()` oversampled samples, for the padding
+    padding_history: [f32; MAX_FACTOR],
+}
+
+impl Oversampler {
+    /// `factor` is rounded down to a power of two between 1 and `MAX_FACTOR`.
+    pub fn new(factor: usize) -> Self {
+        let mut oversampler = Self {
+            stages: vec![HalfbandStage::new(); MAX_FACTOR.ilog2() as usize],
+            num_stages: 0,
+            padding: 0,
+            padding_history: [0.0; MAX_FACTOR],
+        };
+        oversampler.set_factor(factor);
+        oversampler
+    }
+
+    /// Change the factor. The stages are reset when it changes.
+    pub fn set_factor(&mut self, factor: usize) {
+        let num_stages = factor.clamp(1, MAX_FACTOR).ilog2() as usize;
+        if num_stages != self.num_stages {
+            self.num_stages = num_stages;
+            let fraction = self.stage_latency().ceil() - self.stage_latency();
+            self.padding = (fraction * self.factor() as f32).round() as usize;
+            self.reset();
+        }
+    }
+
+    pub fn factor(&self) -> usize {
+        1 << self.num_stages
+    }
+
+    /// The delay of a round trip in samples at the original sample rate, including the
+    /// padding.
+    pub fn latency(&self) -> u32 {
+        self.stage_latency().ceil() as u32
+    }
+
+    /// The delay of a round trip through the stages alone.
+    fn stage_latency(&self) -> f32 {
+        // Both the upsampling and the downsampling filter of a stage delay by half their length
+        (0..self.num_stages)
+            .map(|stage| (2 * TAPS - 2) as f32 / (2 << stage) as f32)
+            .sum()
+    }
+
+    pub fn reset(&mut self) {
+        for stage in &mut self.stages {
+            *stage = HalfbandStage::new();
+        }
+        self.padding_history = [0.0; MAX_FACTOR];
+    }
+
+    /// Upsample `input`, run `f` on every oversampled sample, and downsample the result.
+    pub fn process(&mut self, input: f32, mut f: impl FnMut(f32) -> f32) -> f32 {
+        let mut buffer = [0.0; MAX_FACTOR];
+        self.upsample(input, &mut buffer);
+        for sample in &mut buffer[..self.factor()] {
+            *sample = f(*sample);
+        }
+        self.downsample(&mut buffer)
+    }
+
+    /// Upsample `input` into the first `factor()` samples of `output`.
+    pub fn upsample(&mut self, input: f32, output: &mut [f32; MAX_FACTOR]) {
+        let mut scratch = [0.0; MAX_FACTOR];
+        output[0] = input;
+        let mut len = 1;
+        for stage in &mut self.stages[..self.num_stages] {
+            for i in 0..len {
+                let [even, odd] = stage.upsample(output[i]);
+                scratch[2 * i] = even;
+                scratch[2 * i + 1] = odd;
+            }
+            len *= 2;
+            output[..len].copy_from_slice(&scratch[..len]);
+        }
+    }
+
+    /// Downsample the first `factor()` samples of `input` to a single sample. `input` is used
+    /// as scratch space.
+    pub fn downsample(&mut self, input: &mut [f32; MAX_FACTOR]) -> f32 {
+        let mut len = self.factor();
+        if self.padding > 0 {
+            let delayed: [f32; MAX_FACTOR] =
+                std::array::from_fn(|i| match i.checked_sub(self.padding) {
+                    Some(j) => input[j],
+                    None => self.padding_history[len - self.padding + i],
+                });
+            self.padding_history = *input;
+            *input = delayed;
+        }
+        for stage in self.stages[..self.num_stages].iter_mut().rev() {
+            len /= 2;
+            for i in 0..len {
+                input[i] = stage.downsample([input[2 * i], input[2 * i + 1]]);
+            }
+        }
+        input[0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(freq: f32, t: f32) -> f32 {
+        (2.0 * PI * freq * t / 44100.0).sin()
+    }
+
+    #[test]
+    fn test_factor_and_latency() {
+        assert_eq!(Oversampler::new(1).factor(), 1);
+        assert_eq!(Oversampler::new(1).latency(), 0);
+        assert_eq!(Oversampler::new(2).latency(), 31);
+        // The stages delay by 46.5 and 54.25 samples, which is padded to whole samples
+        assert_eq!(Oversampler::new(4).latency(), 47);
+        assert_eq!(Oversampler::new(8).latency(), 55);
+        assert_eq!(Oversampler::new(6).factor(), 4);
+        assert_eq!(Oversampler::new(16).factor(), 8);
+
+        let mut oversampler = Oversampler::new(8);
+        oversampler.set_factor(2);
+        assert_eq!(oversampler.latency(), 31);
+        assert_eq!(Quality::X4.factor(), 4);
+    }
+
+    #[test]
+    fn test_passes_signal_with_latency() {
+        for factor in [1, 2, 4, 8] {
+            let mut oversampler = Oversampler::new(factor);
+            let latency = oversampler.latency() as f32;
+            for n in 0..4410 {
+                let out = oversampler.process(sine(1000.0, n as f32), |x| x);
+                if n > 200 {
+                    let expected = sine(1000.0, n as f32 - latency);
+                    assert!((out - expected).abs() < 1e-3, "{factor}x: {out} {expected}");
+                }
+            }
+        }
+    }
+
+    /// Amplitude of a frequency component, measured by correlating with a complex sinusoid.
+    fn amplitude(signal: &[f32], freq: f32) -> f32 {
+        let (mut re, mut im) = (0.0, 0.0);
+        for (n, x) in signal.iter().enumerate() {
+            let phase = 2.0 * PI * freq * n as f32 / 44100.0;
+            re += x * phase.cos();
+            im += x * phase.sin();
+        }
+        2.0 * (re * re + im * im).sqrt() / signal.len() as f32
+    }
+
+    #[test]
+    fn test_suppresses_aliasing() {
+        // Cubing a 15 kHz sine creates a 45 kHz harmonic that aliases to 900 Hz at 44.1 kHz
+        let run = |factor: usize| {
+            let mut oversampler = Oversampler::new(factor);
+            let out: Vec<f32> = (0..44100)
+                .map(|n| oversampler.process(sine(15000.0, n as f32), |x| x * x * x))
+                .collect();
+            amplitude(&out[4410..], 900.0)
+        };
+        assert!(run(1) > 0.2);
+        assert!(run(4) < 0.01);
+    }
+}
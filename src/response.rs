@@ -0,0 +1,189 @@
+// Frequency response of the wet path, handed from the audio thread to the editor
+
+use triple_buffer::{triple_buffer, Input, Output};
+
+use crate::wet::{WetPath, WetSettings, WetSnapshot};
+
+/// The response is computed at quarter semitone steps over ten octaves.
+const POINTS_PER_SEMITONE: usize = 4;
+pub const RESPONSE_POINTS: usize = 10 * 12 * POINTS_PER_SEMITONE + 1;
+/// A4 four and a half octaves down, so that every note of standard tuning falls on a point.
+pub const MIN_FREQ: f32 = 19.445_436;
+pub const MAX_FREQ: f32 = MIN_FREQ * 1024.0;
+/// The response is recomputed at most this many times per second.
+const UPDATE_RATE: f32 = 30.0;
+
+/// The frequency of the response's `i`th point.
+pub fn response_freq(i: usize) -> f32 {
+    MIN_FREQ * 2.0_f32.powf(i as f32 / (12 * POINTS_PER_SEMITONE) as f32)
+}
+
+/// The wet path's gain and phase shift at every point of `response_freq()`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Response {
+    /// Gain in decibels. Frequencies that are removed completely are negative infinity.
+    pub magnitude_db: [f32; RESPONSE_POINTS],
+    /// Phase shift in radians
+    pub phase: [f32; RESPONSE_POINTS],
+}
+
+impl Default for Response {
+    fn default() -> Self {
+        Self {
+            magnitude_db: [f32::NEG_INFINITY; RESPONSE_POINTS],
+            phase: [0.0; RESPONSE_POINTS],
+        }
+    }
+}
+
+impl Response {
+    /// Evaluate the response of the wet path `wet` was taken of.
+    pub fn compute(&mut self, wet: &WetSnapshot) {
+        for (i, (magnitude_db, phase)) in self
+            .magnitude_db
+            .iter_mut()
+            .zip(self.phase.iter_mut())
+            .enumerate()
+        {
+            let response = wet.response(response_freq(i));
+            *magnitude_db = 20.0 * response.norm().log10();
+            *phase = response.arg();
+        }
+    }
+}
+
+/// Publishes snapshots of the wet path through a triple buffer, so neither the audio thread nor
+/// the editor ever waits for the other. Evaluating the response is left to the editor, and a
+/// new snapshot is only taken when the settings, the note gains or the frequency list have
+/// changed.
+pub struct ResponsePublisher {
+    input: Input<WetSnapshot>,
+    /// Samples between two updates
+    interval: usize,
+    countdown: usize,
+    /// The settings, gains and frequency list generation of the last published snapshot
+    published: Option<(WetSettings, [f32; 12], u32)>,
+}
+
+impl ResponsePublisher {
+    /// Create the publisher and the output the editor reads from.
+    pub fn new(sample_rate: f32) -> (Self, Output<WetSnapshot>) {
+        let (input, output) = triple_buffer(&WetSnapshot::default());
+        let mut publisher = Self {
+            input,
+            interval: 0,
+            countdown: 0,
+            published: None,
+        };
+        publisher.set_sample_rate(sample_rate);
+        (publisher, output)
+    }
+
+    /// Change the sample rate. The next update always publishes a new snapshot.
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.interval = (sample_rate / UPDATE_RATE) as usize;
+        self.countdown = 0;
+        self.published = None;
+    }
+
+    /// Call after every block of `num_samples` samples with the settings and gains `wet` was
+    /// configured with. This doesn't allocate.
+    pub fn update(
+        &mut self,
+        wet: &WetPath,
+        settings: &WetSettings,
+        gains: [f32; 12],
+        num_samples: usize,
+    ) {
+        self.countdown = self.countdown.saturating_sub(num_samples);
+        let current = Some((*settings, gains, wet.list_generation()));
+        if self.countdown > 0 || current == self.published {
+            return;
+        }
+        self.countdown = self.interval;
+        self.published = current;
+        wet.snapshot(self.input.input_buffer());
+        self.input.publish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filterbank::Target;
+    use crate::frequency_list::Resonance;
+
+    fn read(output: &mut Output<WetSnapshot>) -> Response {
+        let mut response = Response::default();
+        response.compute(output.read());
+        response
+    }
+
+    #[test]
+    fn test_response_freqs() {
+        assert_eq!(response_freq(0), MIN_FREQ);
+        assert!((response_freq(RESPONSE_POINTS - 1) - MAX_FREQ).abs() < 0.1);
+        assert!(response_freq(1) > response_freq(0));
+    }
+
+    #[test]
+    fn test_publishes_changes() {
+        let sr = 44100.0;
+        let (mut publisher, mut output) = ResponsePublisher::new(sr);
+        let settings = WetSettings::default();
+        let mut wet = WetPath::new(sr);
+        wet.configure(&settings);
+        let mut gains = [0.0; 12];
+        wet.set_gains(gains);
+        publisher.update(&wet, &settings, gains, 64);
+        assert!(output.updated());
+        let silent = read(&mut output);
+        assert!(silent.magnitude_db.iter().all(|db| *db < -100.0));
+
+        // Changes within the update interval wait for the interval to pass
+        gains[9] = 1.0;
+        wet.set_gains(gains);
+        publisher.update(&wet, &settings, gains, 64);
+        assert!(!output.updated());
+        publisher.update(&wet, &settings, gains, sr as usize);
+        assert!(output.updated());
+        let response = read(&mut output);
+        let a4 = 54 * POINTS_PER_SEMITONE;
+        assert!((response_freq(a4) - 440.0).abs() < 0.01);
+        assert!((response.magnitude_db[a4] - 20.0 * 9.0_f32.log10()).abs() < 0.1);
+        assert!(response.magnitude_db[a4 + 2] < 0.0);
+
+        // Nothing is recomputed while the settings stay the same
+        publisher.update(&wet, &settings, gains, sr as usize);
+        assert!(!output.updated());
+    }
+
+    #[test]
+    fn test_publishes_list_edits() {
+        let sr = 44100.0;
+        let (mut publisher, mut output) = ResponsePublisher::new(sr);
+        let settings = WetSettings {
+            target: Target::List,
+            ..WetSettings::default()
+        };
+        let mut wet = WetPath::new(sr);
+        wet.configure(&settings);
+        let resonance = |freq: f32| Resonance {
+            freq,
+            gain: 1.0,
+            q: 30.0,
+        };
+        wet.set_frequency_list(&[resonance(1000.0)]);
+        publisher.update(&wet, &settings, [1.0; 12], sr as usize);
+        assert!(output.updated());
+        let first = read(&mut output);
+
+        // Only the list changes, and the plot follows it
+        wet.set_frequency_list(&[resonance(2000.0)]);
+        publisher.update(&wet, &settings, [1.0; 12], sr as usize);
+        assert!(output.updated());
+        assert_ne!(read(&mut output), first);
+        publisher.update(&wet, &settings, [1.0; 12], sr as usize);
+        assert!(!output.updated());
+    }
+}
@@ -0,0 +1,166 @@
+// Drive stage for the resonant signal
+
+use nih_plug::prelude::Enum;
+
+use crate::oversampling::Oversampler;
+
+/// The saturation runs at four times the sample rate to keep aliasing down.
+const OVERSAMPLING: usize = 4;
+/// Operating point of the tube curve. This makes positive and negative half-waves clip
+/// differently, which adds even harmonics.
+const TUBE_BIAS: f32 = 0.3;
+/// Cutoff of the DC blocker after the asymmetric curve, in Hz.
+const DC_BLOCKER_FREQ: f32 = 10.0;
+
+/// How the resonances are saturated.
+#[derive(Enum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Character {
+    /// No saturation
+    #[id = "clean"]
+    Clean,
+    /// Symmetric tanh soft clipping
+    #[id = "soft"]
+    Soft,
+    /// Asymmetric soft clipping
+    #[id = "tube"]
+    Tube,
+}
+
+impl Character {
+    /// The transfer curve. Both curves have unity gain for small signals.
+    fn shape(self, x: f32) -> f32 {
+        match self {
+            Character::Clean => x,
+            Character::Soft => x.tanh(),
+            Character::Tube => {
+                let bias = TUBE_BIAS.tanh();
+                ((x + TUBE_BIAS).tanh() - bias) / (1.0 - bias * bias)
+            }
+        }
+    }
+}
+
+/// Oversampled waveshaper. The signal is driven into the curve and scaled back down by the
+/// same amount, so quiet signals pass unchanged while loud resonances are compressed.
+#[derive(Clone, Debug)]
+pub struct Saturator {
+    character: Character,
+    drive: f32,
+    oversampler: Oversampler,
+    dc_coeff: f32,
+    dc_x1: f32,
+    dc_y1: f32,
+}
+
+impl Saturator {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            character: Character::Clean,
+            drive: 1.0,
+            oversampler: Oversampler::new(OVERSAMPLING),
+            dc_coeff: (-2.0 * std::f32::consts::PI * DC_BLOCKER_FREQ / sample_rate).exp(),
+            dc_x1: 0.0,
+            dc_y1: 0.0,
+        }
+    }
+
+    /// Set the character and the drive in decibels.
+    pub fn set_params(&mut self, character: Character, drive_db: f32) {
+        if character != self.character {
+            self.reset();
+        }
+        self.character = character;
+        self.drive = 10.0_f32.powf(drive_db / 20.0);
+    }
+
+    /// The delay added by the oversampling. `Clean` adds no latency.
+    pub fn latency(&self) -> u32 {
+        match self.character {
+            Character::Clean => 0,
+            _ => self.oversampler.latency(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.oversampler.reset();
+        self.dc_x1 = 0.0;
+        self.dc_y1 = 0.0;
+    }
+
+    pub fn process_sample(&mut self, input: f32) -> f32 {
+        let (character, drive) = (self.character, self.drive);
+        match character {
+            Character::Clean => input,
+            Character::Soft => self
+                .oversampler
+                .process(input, |x| character.shape(x * drive) / drive),
+            Character::Tube => {
+                let shaped = self
+                    .oversampler
+                    .process(input, |x| character.shape(x * drive) / drive);
+                // The asymmetric curve produces a DC offset
+                let output = shaped - self.dc_x1 + self.dc_coeff * self.dc_y1;
+                self.dc_x1 = shaped;
+                self.dc_y1 = output;
+                output
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    fn run(character: Character, drive_db: f32, amplitude: f32) -> Vec<f32> {
+        let mut saturator = Saturator::new(44100.0);
+        saturator.set_params(character, drive_db);
+        (0..44100)
+            .map(|n| {
+                saturator.process_sample(amplitude * (2.0 * PI * 220.0 * n as f32 / 44100.0).sin())
+            })
+            .collect()
+    }
+
+    fn peak(signal: &[f32]) -> f32 {
+        signal.iter().fold(0.0, |a, b| a.max(b.abs()))
+    }
+
+    #[test]
+    fn test_clean_is_transparent() {
+        let mut saturator = Saturator::new(44100.0);
+        assert_eq!(saturator.latency(), 0);
+        assert_eq!(saturator.process_sample(0.25), 0.25);
+    }
+
+    #[test]
+    fn test_quiet_signals_pass() {
+        let out = run(Character::Soft, 0.0, 0.01);
+        assert!((peak(&out[4410..]) - 0.01).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_loud_signals_are_compressed() {
+        for character in [Character::Soft, Character::Tube] {
+            let gentle = peak(&run(character, 0.0, 2.0)[4410..]);
+            let driven = peak(&run(character, 12.0, 2.0)[4410..]);
+            assert!(gentle < 2.0, "{character:?}");
+            assert!(driven < gentle, "{character:?}");
+        }
+    }
+
+    #[test]
+    fn test_tube_has_no_dc() {
+        let out = run(Character::Tube, 12.0, 1.0);
+        let mean = out[22050..].iter().sum::<f32>() / 22050.0;
+        assert!(mean.abs() < 1e-3, "{mean}");
+    }
+
+    #[test]
+    fn test_latency() {
+        let mut saturator = Saturator::new(44100.0);
+        saturator.set_params(Character::Tube, 0.0);
+        assert_eq!(saturator.latency(), 47);
+    }
+}
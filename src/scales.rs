@@ -0,0 +1,206 @@
+// Built-in scale library used to fill the twelve note gains
+
+use nih_plug::prelude::Enum;
+
+/// Scales and modes that can be selected instead of setting every note by hand.
+/// `Custom` keeps the twelve note parameters as they are.
+#[derive(Enum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scale {
+    #[id = "custom"]
+    Custom,
+    #[id = "major"]
+    Major,
+    #[id = "dorian"]
+    Dorian,
+    #[id = "phrygian"]
+    Phrygian,
+    #[id = "lydian"]
+    Lydian,
+    #[id = "mixolydian"]
+    Mixolydian,
+    #[id = "aeolian"]
+    #[name = "Natural Minor"]
+    Aeolian,
+    #[id = "locrian"]
+    Locrian,
+    #[id = "harmonic_minor"]
+    #[name = "Harmonic Minor"]
+    HarmonicMinor,
+    #[id = "melodic_minor"]
+    #[name = "Melodic Minor"]
+    MelodicMinor,
+    #[id = "major_pentatonic"]
+    #[name = "Major Pentatonic"]
+    MajorPentatonic,
+    #[id = "minor_pentatonic"]
+    #[name = "Minor Pentatonic"]
+    MinorPentatonic,
+    #[id = "blues"]
+    Blues,
+    #[id = "whole_tone"]
+    #[name = "Whole Tone"]
+    WholeTone,
+    #[id = "hirajoshi"]
+    Hirajoshi,
+    #[id = "in_sen"]
+    #[name = "In-Sen"]
+    InSen,
+    #[id = "miyako_bushi"]
+    #[name = "Miyako-Bushi"]
+    MiyakoBushi,
+    #[id = "ryukyu"]
+    Ryukyu,
+    #[id = "raga_bhairav"]
+    #[name = "Raga Bhairav"]
+    RagaBhairav,
+    #[id = "raga_todi"]
+    #[name = "Raga Todi"]
+    RagaTodi,
+    #[id = "raga_marwa"]
+    #[name = "Raga Marwa"]
+    RagaMarwa,
+    #[id = "maqam_hijaz"]
+    #[name = "Maqam Hijaz"]
+    MaqamHijaz,
+    #[id = "maqam_nahawand"]
+    #[name = "Maqam Nahawand"]
+    MaqamNahawand,
+    #[id = "maqam_rast"]
+    #[name = "Maqam Rast"]
+    MaqamRast,
+    #[id = "chromatic"]
+    Chromatic,
+}
+
+/// Root note the selected scale is rotated to.
+#[derive(Enum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Root {
+    #[id = "c"]
+    C,
+    #[id = "c_sharp"]
+    #[name = "C#"]
+    CSharp,
+    #[id = "d"]
+    D,
+    #[id = "d_sharp"]
+    #[name = "D#"]
+    DSharp,
+    #[id = "e"]
+    E,
+    #[id = "f"]
+    F,
+    #[id = "f_sharp"]
+    #[name = "F#"]
+    FSharp,
+    #[id = "g"]
+    G,
+    #[id = "g_sharp"]
+    #[name = "G#"]
+    GSharp,
+    #[id = "a"]
+    A,
+    #[id = "a_sharp"]
+    #[name = "A#"]
+    ASharp,
+    #[id = "b"]
+    B,
+}
+
+impl Root {
+    /// Semitone offset from C.
+    pub fn semitone(self) -> usize {
+        self.to_index()
+    }
+}
+
+impl Scale {
+    /// Intervals in semitones above the root. Returns `None` for `Custom`.
+    /// Maqamat that use quarter tones are rounded to the nearest semitone.
+    pub fn intervals(self) -> Option<&'static [u8]> {
+        let intervals: &'static [u8] = match self {
+            Scale::Custom => return None,
+            Scale::Major => &[0, 2, 4, 5, 7, 9, 11],
+            Scale::Dorian => &[0, 2, 3, 5, 7, 9, 10],
+            Scale::Phrygian => &[0, 1, 3, 5, 7, 8, 10],
+            Scale::Lydian => &[0, 2, 4, 6, 7, 9, 11],
+            Scale::Mixolydian => &[0, 2, 4, 5, 7, 9, 10],
+            Scale::Aeolian => &[0, 2, 3, 5, 7, 8, 10],
+            Scale::Locrian => &[0, 1, 3, 5, 6, 8, 10],
+            Scale::HarmonicMinor => &[0, 2, 3, 5, 7, 8, 11],
+            Scale::MelodicMinor => &[0, 2, 3, 5, 7, 9, 11],
+            Scale::MajorPentatonic => &[0, 2, 4, 7, 9],
+            Scale::MinorPentatonic => &[0, 3, 5, 7, 10],
+            Scale::Blues => &[0, 3, 5, 6, 7, 10],
+            Scale::WholeTone => &[0, 2, 4, 6, 8, 10],
+            Scale::Hirajoshi => &[0, 2, 3, 7, 8],
+            Scale::InSen => &[0, 1, 5, 7, 10],
+            Scale::MiyakoBushi => &[0, 1, 5, 7, 8],
+            Scale::Ryukyu => &[0, 4, 5, 7, 11],
+            Scale::RagaBhairav => &[0, 1, 4, 5, 7, 8, 11],
+            Scale::RagaTodi => &[0, 1, 3, 6, 7, 8, 11],
+            Scale::RagaMarwa => &[0, 1, 4, 6, 7, 9, 11],
+            Scale::MaqamHijaz => &[0, 1, 4, 5, 7, 8, 10],
+            Scale::MaqamNahawand => &[0, 2, 3, 5, 7, 8, 11],
+            Scale::MaqamRast => &[0, 2, 4, 5, 7, 9, 10],
+            Scale::Chromatic => &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+        };
+        Some(intervals)
+    }
+
+    /// Build the twelve note gains (C..B) for this scale rotated to `root`.
+    /// Returns `None` for `Custom`.
+    pub fn mask(self, root: Root) -> Option<[f32; 12]> {
+        let intervals = self.intervals()?;
+        let mut mask = [0.0; 12];
+        for &interval in intervals {
+            mask[(root.semitone() + interval as usize) % 12] = 1.0;
+        }
+        Some(mask)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_custom_has_no_mask() {
+        assert_eq!(Scale::Custom.mask(Root::C), None);
+    }
+
+    #[test]
+    fn test_c_major_mask() {
+        let mask = Scale::Major.mask(Root::C).unwrap();
+        assert_eq!(
+            mask,
+            [1.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0, 1.0, 0.0, 1.0, 0.0, 1.0]
+        );
+    }
+
+    #[test]
+    fn test_root_rotates_mask() {
+        // A minor shares the notes of C major
+        let c_major = Scale::Major.mask(Root::C).unwrap();
+        let a_minor = Scale::Aeolian.mask(Root::A).unwrap();
+        assert_eq!(c_major, a_minor);
+
+        // D major contains F# and C# but not F or C
+        let d_major = Scale::Major.mask(Root::D).unwrap();
+        assert_eq!(d_major[6], 1.0);
+        assert_eq!(d_major[1], 1.0);
+        assert_eq!(d_major[5], 0.0);
+        assert_eq!(d_major[0], 0.0);
+    }
+
+    #[test]
+    fn test_interval_counts() {
+        for idx in 1..Scale::variants().len() {
+            let scale = Scale::from_index(idx);
+            let mask = scale.mask(Root::C).unwrap();
+            let active = mask.iter().filter(|g| **g > 0.0).count();
+            assert_eq!(active, scale.intervals().unwrap().len());
+            // Every scale contains its root
+            assert_eq!(mask[0], 1.0);
+        }
+    }
+}
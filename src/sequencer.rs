@@ -0,0 +1,270 @@
+// Host-tempo-synced step sequencer switching between note masks
+
+use nih_plug::prelude::Transport;
+use serde::{Deserialize, Serialize};
+
+use crate::chord::{parse_chord, ChordWeights};
+
+/// A note mask that is held for a number of beats of the host's time signature.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Step {
+    pub mask: [f32; 12],
+    pub beats: f64,
+}
+
+/// The sequence of steps. This is stored in the plugin state.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Pattern {
+    pub steps: Vec<Step>,
+}
+
+impl Default for Pattern {
+    /// One bar of 4/4 each of C, Am, F and G.
+    fn default() -> Self {
+        let steps = ["C", "Am", "F", "G"]
+            .iter()
+            .map(|symbol| Step {
+                mask: parse_chord(symbol)
+                    .expect("valid chord symbol")
+                    .mask(ChordWeights::default()),
+                beats: 4.0,
+            })
+            .collect();
+        Self { steps }
+    }
+}
+
+impl Pattern {
+    /// Total length of the pattern in beats.
+    pub fn length_beats(&self) -> f64 {
+        self.steps.iter().map(|step| step.beats.max(0.0)).sum()
+    }
+
+    /// The note mask at a position in the host's timeline, see `TransportInfo::pattern_pos()`.
+    /// The last `crossfade` beats of every step fade linearly into the next step. Returns `None`
+    /// if the pattern is empty.
+    pub fn mask_at(&self, transport: &TransportInfo, crossfade: f64) -> Option<[f32; 12]> {
+        let length = self.length_beats();
+        if length <= 0.0 {
+            return None;
+        }
+
+        let pos = transport.pattern_pos().unwrap_or(0.0).rem_euclid(length);
+        let mut start = 0.0;
+        for (i, step) in self.steps.iter().enumerate() {
+            let beats = step.beats.max(0.0);
+            let end = start + beats;
+            if pos < end || i == self.steps.len() - 1 {
+                let fade = crossfade.clamp(0.0, beats);
+                let fade_start = end - fade;
+                if fade <= 0.0 || pos < fade_start {
+                    return Some(step.mask);
+                }
+
+                let next = &self.steps[(i + 1) % self.steps.len()];
+                let t = ((pos - fade_start) / fade).clamp(0.0, 1.0) as f32;
+                let mut mask = [0.0; 12];
+                for (out, (a, b)) in mask.iter_mut().zip(step.mask.iter().zip(next.mask.iter())) {
+                    *out = a + (b - a) * t;
+                }
+                return Some(mask);
+            }
+            start = end;
+        }
+
+        None
+    }
+}
+
+/// The parts of the host's transport the sequencer needs. This is separate from nih-plug's
+/// `Transport` so the sequencer can be driven by a mock transport in tests.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TransportInfo {
+    pub playing: bool,
+    pub tempo: Option<f64>,
+    /// Position in quarter notes, like nih-plug's
+    pub pos_beats: Option<f64>,
+    /// Where the current bar started in quarter notes, and its number counted from 0
+    pub bar_start_pos_beats: Option<f64>,
+    pub bar_number: Option<i32>,
+    /// Numerator and denominator
+    pub time_sig: Option<(i32, i32)>,
+}
+
+impl TransportInfo {
+    pub fn from_transport(transport: &Transport) -> Self {
+        Self {
+            playing: transport.playing,
+            tempo: transport.tempo,
+            pos_beats: transport.pos_beats(),
+            bar_start_pos_beats: transport.bar_start_pos_beats(),
+            bar_number: transport.bar_number(),
+            time_sig: transport
+                .time_sig_numerator
+                .zip(transport.time_sig_denominator),
+        }
+    }
+
+    /// The transport `samples` samples later. The position only moves while the host is
+    /// playing and has reported its tempo, and the bar and time signature stay the same.
+    pub fn advance(&self, samples: usize, sample_rate: f32) -> Self {
+        match (self.playing, self.tempo, self.pos_beats) {
+            (true, Some(tempo), Some(pos)) => Self {
+                pos_beats: Some(pos + samples as f64 * tempo / 60.0 / sample_rate as f64),
+                ..*self
+            },
+            _ => *self,
+        }
+    }
+
+    /// The position in beats of the time signature, so a 6/8 bar is six beats long. Every bar
+    /// starts at its number times the numerator, which keeps bar-long steps on the bar lines
+    /// when the time signature changes. Without a time signature the position is counted in
+    /// quarter notes, and `None` means the host didn't report a position.
+    pub fn pattern_pos(&self) -> Option<f64> {
+        let pos = self.pos_beats?;
+        let (numerator, denominator) = match self.time_sig {
+            Some((numerator, denominator)) if numerator > 0 && denominator > 0 => {
+                (numerator as f64, denominator as f64)
+            }
+            _ => return Some(pos),
+        };
+        let beats_per_quarter = denominator / 4.0;
+        match (self.bar_start_pos_beats, self.bar_number) {
+            (Some(bar_start), Some(bar)) => {
+                Some(bar as f64 * numerator + (pos - bar_start) * beats_per_quarter)
+            }
+            _ => Some(pos * beats_per_quarter),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(pos_beats: f64) -> TransportInfo {
+        TransportInfo {
+            playing: true,
+            tempo: Some(120.0),
+            pos_beats: Some(pos_beats),
+            ..TransportInfo::default()
+        }
+    }
+
+    fn pattern() -> Pattern {
+        let mut a = [0.0; 12];
+        a[0] = 1.0;
+        let mut b = [0.0; 12];
+        b[7] = 1.0;
+        Pattern {
+            steps: vec![
+                Step {
+                    mask: a,
+                    beats: 4.0,
+                },
+                Step {
+                    mask: b,
+                    beats: 2.0,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_length() {
+        assert_eq!(pattern().length_beats(), 6.0);
+    }
+
+    #[test]
+    fn test_steps_follow_position() {
+        let p = pattern();
+        assert_eq!(p.mask_at(&at(0.0), 0.0).unwrap()[0], 1.0);
+        assert_eq!(p.mask_at(&at(3.9), 0.0).unwrap()[0], 1.0);
+        assert_eq!(p.mask_at(&at(4.0), 0.0).unwrap()[7], 1.0);
+        assert_eq!(p.mask_at(&at(5.5), 0.0).unwrap()[0], 0.0);
+    }
+
+    #[test]
+    fn test_pattern_loops() {
+        let p = pattern();
+        assert_eq!(p.mask_at(&at(6.5), 0.0), p.mask_at(&at(0.5), 0.0));
+        assert_eq!(p.mask_at(&at(16.0), 0.0), p.mask_at(&at(4.0), 0.0));
+    }
+
+    #[test]
+    fn test_crossfade() {
+        let p = pattern();
+        // Halfway through a one beat crossfade at the end of the first step
+        let mask = p.mask_at(&at(3.5), 1.0).unwrap();
+        assert!((mask[0] - 0.5).abs() < 1e-6);
+        assert!((mask[7] - 0.5).abs() < 1e-6);
+        // Before the crossfade starts
+        assert_eq!(p.mask_at(&at(2.5), 1.0).unwrap()[7], 0.0);
+        // The last step fades back into the first one
+        let mask = p.mask_at(&at(5.75), 1.0).unwrap();
+        assert!((mask[0] - 0.75).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_missing_position_uses_first_step() {
+        let p = pattern();
+        let stopped = TransportInfo::default();
+        assert_eq!(p.mask_at(&stopped, 0.0).unwrap()[0], 1.0);
+    }
+
+    #[test]
+    fn test_time_signature() {
+        // 6/8 counts eighth notes, so the third bar starts at quarter note 6 and beat 12
+        let six_eight = TransportInfo {
+            pos_beats: Some(7.5),
+            bar_start_pos_beats: Some(6.0),
+            bar_number: Some(2),
+            time_sig: Some((6, 8)),
+            ..at(0.0)
+        };
+        assert_eq!(six_eight.pattern_pos(), Some(15.0));
+
+        // Two bars of 4/4 followed by 3/4. Counting quarter notes would put the third bar
+        // two thirds of the way into the third three beat step.
+        let three_four = TransportInfo {
+            pos_beats: Some(8.0),
+            bar_start_pos_beats: Some(8.0),
+            bar_number: Some(2),
+            time_sig: Some((3, 4)),
+            ..at(0.0)
+        };
+        assert_eq!(three_four.pattern_pos(), Some(6.0));
+        let mut masks = [[0.0; 12]; 3];
+        for (note, mask) in masks.iter_mut().enumerate() {
+            mask[note] = 1.0;
+        }
+        let p = Pattern {
+            steps: masks.map(|mask| Step { mask, beats: 3.0 }).to_vec(),
+        };
+        assert_eq!(p.mask_at(&three_four, 0.0), Some(masks[2]));
+    }
+
+    #[test]
+    fn test_advance_follows_tempo() {
+        // Half a second at 120 BPM is one beat
+        let moved = at(1.0).advance(22050, 44100.0);
+        assert!((moved.pos_beats.unwrap() - 2.0).abs() < 1e-9);
+        let stopped = TransportInfo {
+            playing: false,
+            ..at(1.0)
+        };
+        assert_eq!(stopped.advance(22050, 44100.0), stopped);
+        let no_tempo = TransportInfo {
+            tempo: None,
+            ..at(1.0)
+        };
+        assert_eq!(no_tempo.advance(22050, 44100.0), no_tempo);
+    }
+
+    #[test]
+    fn test_empty_pattern() {
+        let p = Pattern { steps: Vec::new() };
+        assert_eq!(p.mask_at(&at(1.0), 0.0), None);
+    }
+}
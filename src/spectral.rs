@@ -0,0 +1,395 @@
+// FFT based alternative to the filter bank
+
+use realfft::num_complex::Complex32;
+use realfft::{ComplexToReal, RealFftPlanner, RealToComplex};
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use crate::tuning::Tuning;
+
+/// Size of the STFT frames. At 44.1 kHz the bins are 5.4 Hz apart, about 21 cents at A4. This is
+/// also the engine's latency.
+pub const FFT_SIZE: usize = 8192;
+/// Frames overlap by 75%.
+const OVERLAP: usize = 4;
+const HOP_SIZE: usize = FFT_SIZE / OVERLAP;
+/// Gain at the centre of each note. This matches the filter bank, whose +20 dB peaks minus the
+/// input leave a gain of 9.
+const PEAK_GAIN: f32 = 9.0;
+/// The same note range as the filter bank, C0 to B8.
+const LOWEST_NOTE: f32 = 12.0;
+const HIGHEST_NOTE: f32 = 119.0;
+/// The narrowest a note's raised cosine may get, in bins. The analysis window spreads a sine
+/// over three bins, and narrower masks would only keep part of it.
+const MIN_WIDTH_BINS: f32 = 2.0;
+
+/// Applies a pitch-class comb mask in the frequency domain. The mask is real valued, so every
+/// frame is filtered with zero phase and the engine as a whole is linear phase.
+pub struct SpectralEngine {
+    forward: Arc<dyn RealToComplex<f32>>,
+    inverse: Arc<dyn ComplexToReal<f32>>,
+    /// Square root of a periodic Hann window, used for both analysis and synthesis
+    window: Vec<f32>,
+    input: Vec<f32>,
+    output: Vec<f32>,
+    pos: usize,
+    hop_counter: usize,
+    frame: Vec<f32>,
+    spectrum: Vec<Complex32>,
+    forward_scratch: Vec<Complex32>,
+    inverse_scratch: Vec<Complex32>,
+    /// The pitch classes of the notes below and above every bin, and the bin's weight in each
+    bin_notes: Vec<[u8; 2]>,
+    bin_weights: Vec<[f32; 2]>,
+    mask: SpectralMask,
+}
+
+/// The settings that shape the engine's mask. This is small enough to be copied to the editor,
+/// which evaluates the mask's response from it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpectralMask {
+    sample_rate: f32,
+    gains: [f32; 12],
+    /// Whether the mask cuts the enabled notes instead of passing them
+    inverted: bool,
+    width: f32,
+    tuning: Tuning,
+}
+
+impl Default for SpectralMask {
+    fn default() -> Self {
+        Self {
+            sample_rate: 44100.0,
+            gains: [1.0; 12],
+            inverted: false,
+            width: 50.0,
+            tuning: Tuning::default(),
+        }
+    }
+}
+
+impl SpectralEngine {
+    pub fn new(sample_rate: f32) -> Self {
+        let mut planner = RealFftPlanner::<f32>::new();
+        let forward = planner.plan_fft_forward(FFT_SIZE);
+        let inverse = planner.plan_fft_inverse(FFT_SIZE);
+        let window = (0..FFT_SIZE)
+            .map(|n| (0.5 - 0.5 * (2.0 * PI * n as f32 / FFT_SIZE as f32).cos()).sqrt())
+            .collect();
+        let num_bins = FFT_SIZE / 2 + 1;
+        let mut engine = Self {
+            frame: forward.make_input_vec(),
+            spectrum: forward.make_output_vec(),
+            forward_scratch: forward.make_scratch_vec(),
+            inverse_scratch: inverse.make_scratch_vec(),
+            forward,
+            inverse,
+            window,
+            input: vec![0.0; FFT_SIZE],
+            output: vec![0.0; FFT_SIZE],
+            pos: 0,
+            hop_counter: 0,
+            bin_notes: vec![[0; 2]; num_bins],
+            bin_weights: vec![[0.0; 2]; num_bins],
+            mask: SpectralMask {
+                sample_rate,
+                ..SpectralMask::default()
+            },
+        };
+        engine.update_mask();
+        engine
+    }
+
+    /// The delay of the engine in samples.
+    pub fn latency(&self) -> u32 {
+        FFT_SIZE as u32
+    }
+
+    pub fn reset(&mut self) {
+        self.input.fill(0.0);
+        self.output.fill(0.0);
+        self.pos = 0;
+        self.hop_counter = 0;
+    }
+
+    /// Update the per-note gains. Expects an array of 12 values for C..B.
+    pub fn set_gains(&mut self, gains: [f32; 12]) {
+        self.mask.gains = gains;
+    }
+
+    /// Cut the enabled notes and pass everything else at unity gain.
+    pub fn set_invert(&mut self, inverted: bool) {
+        self.mask.inverted = inverted;
+    }
+
+    /// Set the width of every note's pass band in cents, measured where the gain has dropped by
+    /// 6 dB. At 100 cents the pass bands add up to a flat response.
+    pub fn set_width(&mut self, width: f32) {
+        if width != self.mask.width {
+            self.mask.width = width;
+            self.update_mask();
+        }
+    }
+
+    pub fn set_tuning(&mut self, tuning: Tuning) {
+        if tuning != self.mask.tuning {
+            self.mask.tuning = tuning;
+            self.update_mask();
+        }
+    }
+
+    /// Weight every bin by raised cosines centred on the notes below and above it.
+    fn update_mask(&mut self) {
+        let bin_width = self.mask.sample_rate / FFT_SIZE as f32;
+        for k in 0..self.bin_notes.len() {
+            (self.bin_notes[k], self.bin_weights[k]) = self.mask.note_weights(k as f32 * bin_width);
+        }
+    }
+
+    /// The mask's current settings, see `SpectralMask::response()`.
+    pub fn mask(&self) -> SpectralMask {
+        self.mask
+    }
+
+    /// The gain the mask applies at `freq` Hz. The mask is real valued, so apart from the
+    /// latency there's no phase shift.
+    pub fn response(&self, freq: f32) -> f32 {
+        self.mask.response(freq)
+    }
+
+    /// Process a single sample. The output is delayed by `latency()` samples.
+    pub fn process_sample(&mut self, input: f32) -> f32 {
+        self.input[self.pos] = input;
+        let output = std::mem::replace(&mut self.output[self.pos], 0.0);
+        self.pos = (self.pos + 1) % FFT_SIZE;
+        self.hop_counter += 1;
+        if self.hop_counter == HOP_SIZE {
+            self.hop_counter = 0;
+            self.process_frame();
+        }
+        output
+    }
+
+    fn process_frame(&mut self) {
+        // `pos` now points at the oldest sample
+        for (i, (sample, w)) in self.frame.iter_mut().zip(self.window.iter()).enumerate() {
+            *sample = self.input[(self.pos + i) % FFT_SIZE] * w;
+        }
+        if self
+            .forward
+            .process_with_scratch(
+                &mut self.frame,
+                &mut self.spectrum,
+                &mut self.forward_scratch,
+            )
+            .is_err()
+        {
+            return;
+        }
+
+        for ((bin, notes), weights) in self
+            .spectrum
+            .iter_mut()
+            .zip(self.bin_notes.iter())
+            .zip(self.bin_weights.iter())
+        {
+            *bin *= mask_gain(&self.mask.gains, self.mask.inverted, *notes, *weights);
+        }
+        // The inverse transform requires purely real DC and Nyquist bins
+        self.spectrum[0].im = 0.0;
+        self.spectrum[FFT_SIZE / 2].im = 0.0;
+        if self
+            .inverse
+            .process_with_scratch(
+                &mut self.spectrum,
+                &mut self.frame,
+                &mut self.inverse_scratch,
+            )
+            .is_err()
+        {
+            return;
+        }
+
+        // The squared windows add up to OVERLAP / 2, and the inverse FFT is unnormalized
+        let scale = 2.0 / (OVERLAP as f32 * FFT_SIZE as f32);
+        for (i, (sample, w)) in self.frame.iter().zip(self.window.iter()).enumerate() {
+            self.output[(self.pos + i) % FFT_SIZE] += sample * w * scale;
+        }
+    }
+}
+
+impl SpectralMask {
+    /// The pitch classes of the notes below and above `freq` and their weights. Each note's
+    /// raised cosine reaches zero at `width` cents from its centre, but never closer than
+    /// `MIN_WIDTH_BINS` bins or wider than 100 cents. Otherwise a note that falls between two
+    /// bins would be dropped entirely. At a width of 50 cents this kicks in below about 370 Hz,
+    /// and below about 180 Hz every note gets the full 100 cents.
+    fn note_weights(&self, freq: f32) -> ([u8; 2], [f32; 2]) {
+        let mut notes = [0; 2];
+        let mut weights = [0.0; 2];
+        let freq = freq.max(1.0);
+        let min_cents =
+            1200.0 * (1.0 + MIN_WIDTH_BINS * self.sample_rate / FFT_SIZE as f32 / freq).log2();
+        let width = self.width.max(min_cents).min(100.0);
+        let midi = 69.0 + 12.0 * (freq / self.tuning.reference).log2();
+        let below = midi.floor();
+        for (i, note) in [below, below + 1.0].into_iter().enumerate() {
+            if !(LOWEST_NOTE..=HIGHEST_NOTE).contains(&note) {
+                continue;
+            }
+            let distance = (midi - note) * 100.0 - self.tuning.cents(note as u8);
+            notes[i] = note as u8 % 12;
+            if distance.abs() < width {
+                weights[i] = 0.5 + 0.5 * (PI * distance / width).cos();
+            }
+        }
+        (notes, weights)
+    }
+
+    /// The gain the mask applies at `freq` Hz.
+    pub fn response(&self, freq: f32) -> f32 {
+        let (notes, weights) = self.note_weights(freq);
+        mask_gain(&self.gains, self.inverted, notes, weights)
+    }
+}
+
+/// The gain of a bin between two notes with the given pitch classes and weights.
+fn mask_gain(gains: &[f32; 12], inverted: bool, notes: [u8; 2], weights: [f32; 2]) -> f32 {
+    let mask = weights[0] * gains[notes[0] as usize] + weights[1] * gains[notes[1] as usize];
+    if inverted {
+        (1.0 - mask).max(0.0)
+    } else {
+        PEAK_GAIN * mask
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rms_of_sine(freq: f32, gains: [f32; 12], width: f32) -> f32 {
+        let sr = 44100.0;
+        let mut engine = SpectralEngine::new(sr);
+        engine.set_gains(gains);
+        engine.set_width(width);
+        let out: Vec<f32> = (0..4 * FFT_SIZE)
+            .map(|n| engine.process_sample((2.0 * PI * freq * n as f32 / sr).sin()))
+            .collect();
+        let tail = &out[2 * FFT_SIZE..];
+        (tail.iter().map(|x| x * x).sum::<f32>() / tail.len() as f32).sqrt()
+    }
+
+    fn only(note: usize) -> [f32; 12] {
+        let mut gains = [0.0; 12];
+        gains[note] = 1.0;
+        gains
+    }
+
+    #[test]
+    fn test_enabled_note_passes() {
+        // A sine has an RMS of 1/sqrt(2). The bins next to the note are weighted slightly less.
+        let rms = rms_of_sine(440.0, only(9), 100.0);
+        let expected = PEAK_GAIN / 2.0_f32.sqrt();
+        assert!(rms > 0.9 * expected && rms <= expected, "{rms}");
+    }
+
+    #[test]
+    fn test_all_notes_pass_everything() {
+        let gains = [1.0; 12];
+        for freq in [100.0, 440.0, 452.9, 3000.0] {
+            let rms = rms_of_sine(freq, gains, 100.0);
+            assert!(
+                (rms - PEAK_GAIN / 2.0_f32.sqrt()).abs() < 0.01,
+                "{freq}: {rms}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_other_notes_are_removed() {
+        let on = rms_of_sine(440.0, only(9), 50.0);
+        let off = rms_of_sine(466.16, only(9), 50.0);
+        assert!(off < 0.01 * on, "{off} {on}");
+    }
+
+    #[test]
+    fn test_invert_cuts_enabled_notes() {
+        let run = |freq: f32| {
+            let sr = 44100.0;
+            let mut engine = SpectralEngine::new(sr);
+            engine.set_gains(only(9));
+            engine.set_invert(true);
+            // The full width leaves no gaps between the notch's bins
+            engine.set_width(100.0);
+            let out: Vec<f32> = (0..4 * FFT_SIZE)
+                .map(|n| engine.process_sample((2.0 * PI * freq * n as f32 / sr).sin()))
+                .collect();
+            let tail = &out[2 * FFT_SIZE..];
+            (tail.iter().map(|x| x * x).sum::<f32>() / tail.len() as f32).sqrt()
+        };
+        let sine_rms = 1.0 / 2.0_f32.sqrt();
+        assert!(run(440.0) < 0.05 * sine_rms);
+        assert!((run(523.25) - sine_rms).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_response_matches_mask() {
+        let mut engine = SpectralEngine::new(44100.0);
+        engine.set_gains(only(9));
+        engine.set_width(50.0);
+        assert_eq!(engine.response(440.0), PEAK_GAIN);
+        assert_eq!(engine.response(880.0), PEAK_GAIN);
+        assert_eq!(engine.response(466.16), 0.0);
+        // A sine between the bins' centres is measured slightly lower
+        let rms = rms_of_sine(440.0, only(9), 50.0);
+        let expected = engine.response(440.0) / 2.0_f32.sqrt();
+        assert!(rms > 0.9 * expected && rms <= expected, "{rms}");
+        engine.set_invert(true);
+        assert_eq!(engine.response(440.0), 0.0);
+        assert_eq!(engine.response(466.16), 1.0);
+    }
+
+    #[test]
+    fn test_width_controls_selectivity() {
+        // 30 cents sharp is outside a 50 cent wide band but inside a 100 cent wide band
+        let freq = 440.0 * 2.0_f32.powf(30.0 / 1200.0);
+        let narrow = rms_of_sine(freq, only(9), 50.0);
+        let wide = rms_of_sine(freq, only(9), 100.0);
+        assert!(narrow < 0.5 * wide, "{narrow} {wide}");
+    }
+
+    #[test]
+    fn test_low_notes_pass_at_narrow_widths() {
+        // A2 falls between two bins that are 37 and 47 cents away, so a 10 cent wide mask would
+        // miss it without the minimum width. Two bins are more than a semitone here, so the
+        // engine falls back to the widest mask that still keeps the notes apart.
+        let narrow = rms_of_sine(110.0, only(9), 10.0);
+        let wide = rms_of_sine(110.0, only(9), 100.0);
+        let expected = PEAK_GAIN / 2.0_f32.sqrt();
+        assert!(narrow > 0.5 * expected, "{narrow}");
+        assert!((narrow - wide).abs() < 1e-3 * expected, "{narrow} {wide}");
+        // The width only grows where it has to, so A4 is still narrower than a semitone
+        let sharp = 440.0 * 2.0_f32.powf(60.0 / 1200.0);
+        assert!(rms_of_sine(sharp, only(9), 10.0) < 0.1 * expected);
+    }
+
+    #[test]
+    fn test_latency() {
+        let sr = 44100.0;
+        let mut engine = SpectralEngine::new(sr);
+        engine.set_gains([1.0; 12]);
+        engine.set_width(100.0);
+        let latency = engine.latency() as usize;
+        let freq = 440.0;
+        for n in 0..4 * FFT_SIZE {
+            let out = engine.process_sample((2.0 * PI * freq * n as f32 / sr).sin());
+            if n >= 2 * FFT_SIZE {
+                let expected = PEAK_GAIN * (2.0 * PI * freq * (n - latency) as f32 / sr).sin();
+                assert!(
+                    (out - expected).abs() < 0.05 * PEAK_GAIN,
+                    "{out} {expected}"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,124 @@
+// Transient detection used to keep drum hits out of the filter bank
+
+/// Time constants of the fast and slow power envelopes in milliseconds.
+const FAST_MS: f32 = 1.0;
+const SLOW_MS: f32 = 30.0;
+/// Smoothing of the transient weight in milliseconds, to avoid zipper noise.
+const WEIGHT_MS: f32 = 2.0;
+/// The fast envelope has to exceed the slow one by this power ratio (3 dB) before anything
+/// counts as a transient. This keeps the ripple of low notes from being detected.
+const RATIO_THRESHOLD: f32 = 2.0;
+/// Above the threshold, the weight reaches 1 after this much more power ratio.
+const RATIO_RANGE: f32 = 2.0;
+
+fn one_pole_coeff(time_ms: f32, sample_rate: f32) -> f32 {
+    (-1000.0 / (time_ms * sample_rate)).exp()
+}
+
+/// Splits a signal into a transient and a sustained part by comparing a fast and a slow power
+/// envelope. Onsets raise the fast envelope well above the slow one.
+#[derive(Clone, Debug)]
+pub struct TransientSplitter {
+    fast_coeff: f32,
+    slow_coeff: f32,
+    weight_coeff: f32,
+    fast: f32,
+    slow: f32,
+    weight: f32,
+}
+
+impl TransientSplitter {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            fast_coeff: one_pole_coeff(FAST_MS, sample_rate),
+            slow_coeff: one_pole_coeff(SLOW_MS, sample_rate),
+            weight_coeff: one_pole_coeff(WEIGHT_MS, sample_rate),
+            fast: 0.0,
+            slow: 0.0,
+            weight: 0.0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.fast = 0.0;
+        self.slow = 0.0;
+        self.weight = 0.0;
+    }
+
+    /// How much of the current sample is transient, between 0 and 1.
+    pub fn weight(&self) -> f32 {
+        self.weight
+    }
+
+    /// Update the detector and return the transient part of `input`. The sustained part is
+    /// `input` minus the returned value.
+    pub fn process_sample(&mut self, input: f32) -> f32 {
+        let power = input * input;
+        self.fast = power + self.fast_coeff * (self.fast - power);
+        self.slow = power + self.slow_coeff * (self.slow - power);
+
+        let ratio = self.fast / (self.slow + 1e-12);
+        let target = if self.fast > 1e-10 {
+            ((ratio - RATIO_THRESHOLD) / RATIO_RANGE).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        self.weight = target + self.weight_coeff * (self.weight - target);
+        input * self.weight
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    #[test]
+    fn test_onset_is_transient() {
+        let mut splitter = TransientSplitter::new(44100.0);
+        let mut weights = Vec::new();
+        for n in 0..44100 {
+            splitter.process_sample((2.0 * PI * 220.0 * n as f32 / 44100.0).sin());
+            weights.push(splitter.weight());
+        }
+        // The first few milliseconds of the note are treated as a transient
+        assert!(weights[..441].iter().cloned().fold(0.0, f32::max) > 0.9);
+        // The sustained note is not
+        assert!(weights[4410..].iter().all(|w| *w < 1e-3));
+    }
+
+    #[test]
+    fn test_low_notes_are_not_transient() {
+        let mut splitter = TransientSplitter::new(44100.0);
+        for n in 0..44100 {
+            splitter.process_sample((2.0 * PI * 55.0 * n as f32 / 44100.0).sin());
+            if n > 8820 {
+                assert!(splitter.weight() < 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn test_repeated_hits() {
+        let mut splitter = TransientSplitter::new(44100.0);
+        let mut transient_energy = 0.0;
+        let mut energy = 0.0;
+        for n in 0..44100 {
+            // A decaying noise-like burst every 250 ms
+            let t = (n % 11025) as f32 / 44100.0;
+            let x = (-t * 80.0).exp() * (n as f32 * 1.7).sin();
+            let transient = splitter.process_sample(x);
+            transient_energy += transient * transient;
+            energy += x * x;
+        }
+        assert!(transient_energy > 0.3 * energy);
+    }
+
+    #[test]
+    fn test_silence() {
+        let mut splitter = TransientSplitter::new(44100.0);
+        for _ in 0..100 {
+            assert_eq!(splitter.process_sample(0.0), 0.0);
+        }
+    }
+}
@@ -0,0 +1,350 @@
+// Per-note detuning, stretched tuning and tuning detection for the filter bank
+
+use nih_plug::prelude::*;
+use realfft::num_complex::Complex32;
+use realfft::{RealFftPlanner, RealToComplex};
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+/// FFT size used for tuning detection. At 44.1 kHz this gives 5.4 Hz bins, which is refined
+/// further with parabolic interpolation.
+const ANALYSIS_SIZE: usize = 8192;
+/// One histogram bin per cent of deviation from equal temperament.
+const HISTOGRAM_BINS: usize = 100;
+/// How much of the histogram is kept for every new analysis window.
+const HISTOGRAM_DECAY: f32 = 0.95;
+/// Spectral peaks more than 40 dB below the loudest peak are ignored.
+const PEAK_THRESHOLD: f32 = 0.01;
+/// Only partials in this range are used for the estimate.
+const MIN_PARTIAL_FREQ: f32 = 50.0;
+const MAX_PARTIAL_FREQ: f32 = 5000.0;
+
+/// Centre frequency offsets applied to the filter bank.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tuning {
+    /// Frequency of A4 in Hz.
+    pub reference: f32,
+    /// Offset in cents for each pitch class C..B.
+    pub detune: [f32; 12],
+    /// Amount of stretched tuning. 1 approximates the Railsback curve of a typical piano.
+    pub stretch: f32,
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Self {
+            reference: 440.0,
+            detune: [0.0; 12],
+            stretch: 0.0,
+        }
+    }
+}
+
+impl Tuning {
+    /// The total offset in cents for a MIDI note.
+    pub fn cents(&self, midi: u8) -> f32 {
+        self.detune[midi as usize % 12] + self.stretch * railsback_cents(midi as f32)
+    }
+}
+
+/// Approximation of the Railsback curve: the deviation in cents from equal temperament of a
+/// tuned piano. The curve is modelled as a parabola on either side of A4, reaching roughly
+/// -30 cents at A0 and +30 cents at C8.
+pub fn railsback_cents(midi: f32) -> f32 {
+    let octaves = (midi - 69.0) / 12.0;
+    if octaves >= 0.0 {
+        3.0 * octaves * octaves
+    } else {
+        -1.8 * octaves * octaves
+    }
+}
+
+/// Estimates how far the input's tuning deviates from 12-TET at A4 = 440 Hz. The spectral peaks
+/// of every analysis window are collected in a histogram of their deviation in cents, and the
+/// estimate is the circular mean of that histogram.
+pub struct TuningEstimator {
+    sample_rate: f32,
+    fft: Arc<dyn RealToComplex<f32>>,
+    window: Vec<f32>,
+    input: Vec<f32>,
+    input_pos: usize,
+    fft_input: Vec<f32>,
+    spectrum: Vec<Complex32>,
+    fft_scratch: Vec<Complex32>,
+    magnitudes: Vec<f32>,
+    histogram: [f32; HISTOGRAM_BINS],
+}
+
+impl TuningEstimator {
+    pub fn new(sample_rate: f32) -> Self {
+        let fft = RealFftPlanner::<f32>::new().plan_fft_forward(ANALYSIS_SIZE);
+        let window = (0..ANALYSIS_SIZE)
+            .map(|n| 0.5 - 0.5 * (2.0 * PI * n as f32 / ANALYSIS_SIZE as f32).cos())
+            .collect();
+        Self {
+            sample_rate,
+            fft_input: fft.make_input_vec(),
+            spectrum: fft.make_output_vec(),
+            fft_scratch: fft.make_scratch_vec(),
+            magnitudes: vec![0.0; ANALYSIS_SIZE / 2 + 1],
+            fft,
+            window,
+            input: vec![0.0; ANALYSIS_SIZE],
+            input_pos: 0,
+            histogram: [0.0; HISTOGRAM_BINS],
+        }
+    }
+
+    /// Forget everything that has been learned so far.
+    pub fn reset(&mut self) {
+        self.input_pos = 0;
+        self.histogram = [0.0; HISTOGRAM_BINS];
+    }
+
+    /// Feed a sample of the input. An analysis runs every half window.
+    pub fn process_sample(&mut self, sample: f32) {
+        self.input[self.input_pos] = sample;
+        self.input_pos += 1;
+        if self.input_pos == ANALYSIS_SIZE {
+            self.analyze();
+            self.input.copy_within(ANALYSIS_SIZE / 2.., 0);
+            self.input_pos = ANALYSIS_SIZE / 2;
+        }
+    }
+
+    fn analyze(&mut self) {
+        for ((out, x), w) in self
+            .fft_input
+            .iter_mut()
+            .zip(self.input.iter())
+            .zip(self.window.iter())
+        {
+            *out = x * w;
+        }
+        if self
+            .fft
+            .process_with_scratch(
+                &mut self.fft_input,
+                &mut self.spectrum,
+                &mut self.fft_scratch,
+            )
+            .is_err()
+        {
+            return;
+        }
+
+        let mut max_magnitude = 0.0_f32;
+        for (m, bin) in self.magnitudes.iter_mut().zip(self.spectrum.iter()) {
+            *m = bin.norm();
+            max_magnitude = max_magnitude.max(*m);
+        }
+        // Ignore silence
+        if max_magnitude < 1e-3 {
+            return;
+        }
+
+        for h in &mut self.histogram {
+            *h *= HISTOGRAM_DECAY;
+        }
+        let bin_width = self.sample_rate / ANALYSIS_SIZE as f32;
+        let threshold = max_magnitude * PEAK_THRESHOLD;
+        for k in 1..self.magnitudes.len() - 1 {
+            let (a, b, c) = (
+                self.magnitudes[k - 1],
+                self.magnitudes[k],
+                self.magnitudes[k + 1],
+            );
+            if b < threshold || b <= a || b < c {
+                continue;
+            }
+
+            // Parabolic interpolation on the log magnitudes gives a sub-bin frequency estimate
+            let (la, lb, lc) = (a.max(1e-12).ln(), b.ln(), c.max(1e-12).ln());
+            let denominator = la - 2.0 * lb + lc;
+            let offset = if denominator.abs() > 1e-12 {
+                (0.5 * (la - lc) / denominator).clamp(-0.5, 0.5)
+            } else {
+                0.0
+            };
+            let freq = (k as f32 + offset) * bin_width;
+            if !(MIN_PARTIAL_FREQ..=MAX_PARTIAL_FREQ).contains(&freq) {
+                continue;
+            }
+
+            let cents = 1200.0 * (freq / 440.0).log2();
+            let deviation = cents - (cents / 100.0).round() * 100.0;
+            let bin = ((deviation + 50.0) as usize).min(HISTOGRAM_BINS - 1);
+            self.histogram[bin] += b / max_magnitude;
+        }
+    }
+
+    /// The estimated tuning offset in cents relative to A4 = 440 Hz, or `None` if nothing has
+    /// been analyzed yet.
+    pub fn estimate_cents(&self) -> Option<f32> {
+        let (mut sum_cos, mut sum_sin, mut total) = (0.0_f32, 0.0_f32, 0.0_f32);
+        for (bin, weight) in self.histogram.iter().enumerate() {
+            // The deviation wraps around at +-50 cents, so this is averaged on a circle
+            let angle = 2.0 * PI * (bin as f32 + 0.5 - 50.0) / HISTOGRAM_BINS as f32;
+            sum_cos += weight * angle.cos();
+            sum_sin += weight * angle.sin();
+            total += weight;
+        }
+        if total < 1e-3 {
+            return None;
+        }
+        Some(sum_sin.atan2(sum_cos) * HISTOGRAM_BINS as f32 / (2.0 * PI))
+    }
+}
+
+/// Twelve per-note detune amounts in cents.
+#[derive(Params)]
+pub struct DetuneParams {
+    #[id = "c"]
+    pub c: FloatParam,
+    #[id = "c_sharp"]
+    pub c_sharp: FloatParam,
+    #[id = "d"]
+    pub d: FloatParam,
+    #[id = "d_sharp"]
+    pub d_sharp: FloatParam,
+    #[id = "e"]
+    pub e: FloatParam,
+    #[id = "f"]
+    pub f: FloatParam,
+    #[id = "f_sharp"]
+    pub f_sharp: FloatParam,
+    #[id = "g"]
+    pub g: FloatParam,
+    #[id = "g_sharp"]
+    pub g_sharp: FloatParam,
+    #[id = "a"]
+    pub a: FloatParam,
+    #[id = "a_sharp"]
+    pub a_sharp: FloatParam,
+    #[id = "b"]
+    pub b: FloatParam,
+}
+
+impl Default for DetuneParams {
+    fn default() -> Self {
+        let param = |note: &str| {
+            FloatParam::new(
+                format!("{note} Detune"),
+                0.0,
+                FloatRange::Linear {
+                    min: -50.0,
+                    max: 50.0,
+                },
+            )
+            .with_unit(" ct")
+            .with_step_size(0.1)
+        };
+        Self {
+            c: param("C"),
+            c_sharp: param("C#"),
+            d: param("D"),
+            d_sharp: param("D#"),
+            e: param("E"),
+            f: param("F"),
+            f_sharp: param("F#"),
+            g: param("G"),
+            g_sharp: param("G#"),
+            a: param("A"),
+            a_sharp: param("A#"),
+            b: param("B"),
+        }
+    }
+}
+
+impl DetuneParams {
+    /// The current offsets in cents for C..B.
+    pub fn cents(&self) -> [f32; 12] {
+        [
+            self.c.value(),
+            self.c_sharp.value(),
+            self.d.value(),
+            self.d_sharp.value(),
+            self.e.value(),
+            self.f.value(),
+            self.f_sharp.value(),
+            self.g.value(),
+            self.g_sharp.value(),
+            self.a.value(),
+            self.a_sharp.value(),
+            self.b.value(),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_railsback_shape() {
+        assert_eq!(railsback_cents(69.0), 0.0);
+        // A0 is flat and C8 is sharp by roughly 30 cents
+        assert!((railsback_cents(21.0) + 28.8).abs() < 0.1);
+        assert!((railsback_cents(108.0) - 31.7).abs() < 0.1);
+        // The curve grows monotonically
+        assert!(railsback_cents(60.0) < railsback_cents(61.0));
+        assert!(railsback_cents(90.0) < railsback_cents(91.0));
+    }
+
+    #[test]
+    fn test_cents_combines_detune_and_stretch() {
+        let mut detune = [0.0; 12];
+        detune[9] = 5.0;
+        let tuning = Tuning {
+            detune,
+            stretch: 0.5,
+            ..Tuning::default()
+        };
+        assert_eq!(tuning.cents(69), 5.0);
+        assert!((tuning.cents(81) - (5.0 + 1.5)).abs() < 1e-6);
+        assert_eq!(tuning.cents(60), 0.5 * railsback_cents(60.0));
+    }
+
+    /// Feed one second of a harmonic tone plus a fifth above it, tuned `cents` away from 12-TET.
+    fn estimate_for(cents: f32) -> Option<f32> {
+        let sr = 44100.0;
+        let mut estimator = TuningEstimator::new(sr);
+        let ratio = 2.0_f32.powf(cents / 1200.0);
+        let fundamentals = [220.0 * ratio, 329.63 * ratio];
+        for n in 0..44100 {
+            let t = n as f32 / sr;
+            let mut sample = 0.0;
+            for f in fundamentals {
+                for partial in 1..=4 {
+                    let p = partial as f32;
+                    sample += (2.0 * PI * f * p * t).sin() / p;
+                }
+            }
+            estimator.process_sample(0.1 * sample);
+        }
+        estimator.estimate_cents()
+    }
+
+    #[test]
+    fn test_estimate_in_tune() {
+        let estimate = estimate_for(0.0).unwrap();
+        assert!(estimate.abs() < 3.0, "{estimate}");
+    }
+
+    #[test]
+    fn test_estimate_sharp_and_flat() {
+        let sharp = estimate_for(20.0).unwrap();
+        assert!((sharp - 20.0).abs() < 3.0, "{sharp}");
+        let flat = estimate_for(-15.0).unwrap();
+        assert!((flat + 15.0).abs() < 3.0, "{flat}");
+    }
+
+    #[test]
+    fn test_estimate_silence() {
+        let mut estimator = TuningEstimator::new(44100.0);
+        for _ in 0..44100 {
+            estimator.process_sample(0.0);
+        }
+        assert_eq!(estimator.estimate_cents(), None);
+    }
+}